@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+/// The target cash ratio of `AMMSellBull` as a function of price.
+#[derive(Clone, Debug)]
+pub enum CashRatioPath {
+    Fixed(f64),
+    /// Linear between the points and flat outside, see `CashRatioPath::piecewise_linear`.
+    PiecewiseLinear(PricePoints),
+    Custom(fn(f64) -> f64),
+}
+
+/// The (price, ratio) points of a `CashRatioPath::PiecewiseLinear`, at least one, sorted by price.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PricePoints(Vec<(f64, f64)>);
+
+impl PricePoints {
+    pub fn new(points: Vec<(f64, f64)>) -> Result<Self, CashRatioPathError> {
+        if points.is_empty() {
+            return Err(CashRatioPathError::NoPoints);
+        }
+        let unsorted = |pair: &[(f64, f64)]| {
+            pair[0]
+                .0
+                .partial_cmp(&pair[1].0)
+                .is_none_or(Ordering::is_gt)
+        };
+        if let Some(index) = points.windows(2).position(unsorted) {
+            return Err(CashRatioPathError::Unsorted { index: index + 1 });
+        }
+
+        Ok(Self(points))
+    }
+
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.0
+    }
+}
+
+/// Errors when building a `CashRatioPath`.
+#[derive(Debug, PartialEq)]
+pub enum CashRatioPathError {
+    NoPoints,
+    /// The point at `index` has a lower price than the one before, or a NaN price.
+    Unsorted {
+        index: usize,
+    },
+}
+
+impl fmt::Display for CashRatioPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CashRatioPathError::NoPoints => write!(f, "no price points"),
+            CashRatioPathError::Unsorted { index } => {
+                write!(f, "price point {} is not sorted by price", index)
+            }
+        }
+    }
+}
+
+impl Error for CashRatioPathError {}
+
+impl CashRatioPath {
+    /// (price, ratio) points sorted by price, e.g. `[(33000.0, 0.25), (100000.0, 0.8)]`.
+    pub fn piecewise_linear(points: Vec<(f64, f64)>) -> Result<Self, CashRatioPathError> {
+        Ok(CashRatioPath::PiecewiseLinear(PricePoints::new(points)?))
+    }
+
+    pub fn ratio_at(&self, price: f64) -> f64 {
+        match self {
+            CashRatioPath::Fixed(ratio) => *ratio,
+            CashRatioPath::PiecewiseLinear(PricePoints(points)) => {
+                let after = points.iter().position(|point| point.0 > price);
+                match after {
+                    Some(0) => points[0].1,
+                    Some(i) => {
+                        let (p0, r0) = points[i - 1];
+                        let (p1, r1) = points[i];
+                        r0 + (r1 - r0) * (price - p0) / (p1 - p0)
+                    }
+                    // Not empty by construction.
+                    None => points[points.len() - 1].1,
+                }
+            }
+            CashRatioPath::Custom(ratio_at) => ratio_at(price),
+        }
+    }
+}
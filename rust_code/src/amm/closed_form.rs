@@ -0,0 +1,73 @@
+// Closed forms of the constant cash ratio ladders in `AMMSellBull` and `AMMBuyBear`, without
+// fees and exchange rules.
+//
+// Levels are geometric, `p_k = p_0 (1 + s)^k` going up and `p_0 (1 - s)^k` going down. Once the
+// AMM holds `r` of its value `V` in cash, moving one level keeps the cash and revalues the
+// coins, so after rebalancing `V_k = V_0 (1 + (1 - r) s)^k` going up and
+// `V_0 (1 - (1 - r) s)^k` going down, with `r V_k` in cash and `(1 - r) V_k / p_k` in coins.
+// When the step goes to zero, `V = V_0 (p / p_0)^(1 - r)`.
+
+/// The number of levels `p_0 (1 + step)^k` below `to_price`, the levels
+/// `AMMSellBull::sell(to_price)` visits.
+pub fn levels_up(from_price: f64, to_price: f64, step: f64) -> usize {
+    if to_price <= from_price {
+        return 0;
+    }
+    ((to_price / from_price).ln() / (1.0 + step).ln()).ceil() as usize
+}
+
+/// The number of levels `p_0 (1 - step)^k` at or above `to_price`, the levels
+/// `AMMBuyBear::buy(to_price)` visits.
+pub fn levels_down(from_price: f64, to_price: f64, step: f64) -> usize {
+    if to_price > from_price {
+        return 0;
+    }
+    ((to_price / from_price).ln() / (1.0 - step).ln()).floor() as usize + 1
+}
+
+/// The value of a rebalanced AMM after `k` levels up.
+pub fn value_after_steps_up(value: f64, cash_ratio: f64, step: f64, k: usize) -> f64 {
+    value * (1.0 + (1.0 - cash_ratio) * step).powi(k as i32)
+}
+
+/// The value of a rebalanced AMM after `k` levels down.
+pub fn value_after_steps_down(value: f64, cash_ratio: f64, step: f64, k: usize) -> f64 {
+    value * (1.0 - (1.0 - cash_ratio) * step).powi(k as i32)
+}
+
+/// The value of a rebalanced AMM moved from `from_price` to `to_price` with infinitely small
+/// steps.
+pub fn continuous_value(value: f64, cash_ratio: f64, from_price: f64, to_price: f64) -> f64 {
+    value * (to_price / from_price).powf(1.0 - cash_ratio)
+}
+
+/// Cash and coins of an AMM with `value` rebalanced at `price`.
+pub fn rebalanced(value: f64, cash_ratio: f64, price: f64) -> (f64, f64) {
+    (value * cash_ratio, value * (1.0 - cash_ratio) / price)
+}
+
+/// The cumulative fills of a ladder rebalanced at `from_price` and moved `k` levels up.
+/// Returns: (cash taken out, coins sold).
+pub fn sell_fills(value: f64, cash_ratio: f64, step: f64, from_price: f64, k: usize) -> (f64, f64) {
+    let price = from_price * (1.0 + step).powi(k as i32);
+    let (cash, coins) = rebalanced(value, cash_ratio, from_price);
+    let (new_cash, new_coins) = rebalanced(
+        value_after_steps_up(value, cash_ratio, step, k),
+        cash_ratio,
+        price,
+    );
+    (new_cash - cash, coins - new_coins)
+}
+
+/// The cumulative fills of a ladder rebalanced at `from_price` and moved `k` levels down.
+/// Returns: (cash spent, coins bought).
+pub fn buy_fills(value: f64, cash_ratio: f64, step: f64, from_price: f64, k: usize) -> (f64, f64) {
+    let price = from_price * (1.0 - step).powi(k as i32);
+    let (cash, coins) = rebalanced(value, cash_ratio, from_price);
+    let (new_cash, new_coins) = rebalanced(
+        value_after_steps_down(value, cash_ratio, step, k),
+        cash_ratio,
+        price,
+    );
+    (cash - new_cash, new_coins - coins)
+}
@@ -0,0 +1,194 @@
+use super::*;
+
+/// The holdings of an AMM as a function of price. Trading along the curve from one price to
+/// another exchanges the differences of the reserves.
+pub trait BondingCurve {
+    /// Returns: (cash, coins) held at `price`.
+    fn reserves_at(&self, price: f64) -> (f64, f64);
+
+    fn value_at(&self, price: f64) -> f64 {
+        let (cash, coins) = self.reserves_at(price);
+        cash + coins * price
+    }
+}
+
+/// So curves of different kinds can be compared in one list.
+impl<C: BondingCurve + ?Sized> BondingCurve for Box<C> {
+    fn reserves_at(&self, price: f64) -> (f64, f64) {
+        (**self).reserves_at(price)
+    }
+}
+
+/// `cash * coins = k`, half of the value in cash.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ConstantProduct {
+    k: f64,
+}
+
+impl ConstantProduct {
+    /// A curve holding `value` at `price`.
+    pub fn new(value: f64, price: f64) -> Self {
+        let cash = value / 2.0;
+        Self {
+            k: cash * cash / price,
+        }
+    }
+}
+
+impl BondingCurve for ConstantProduct {
+    fn reserves_at(&self, price: f64) -> (f64, f64) {
+        ((self.k * price).sqrt(), (self.k / price).sqrt())
+    }
+}
+
+/// Keep `cash_ratio` of the value in cash, the ladders of `AMMSellBull` and `AMMBuyBear` with
+/// infinitely small steps, see `closed_form::continuous_value`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ConstantRatio {
+    value: f64,
+    price: f64,
+    cash_ratio: f64,
+}
+
+impl ConstantRatio {
+    pub fn new(value: f64, price: f64, cash_ratio: f64) -> Self {
+        Self {
+            value,
+            price,
+            cash_ratio,
+        }
+    }
+}
+
+impl BondingCurve for ConstantRatio {
+    fn reserves_at(&self, price: f64) -> (f64, f64) {
+        let value = closed_form::continuous_value(self.value, self.cash_ratio, self.price, price);
+        closed_form::rebalanced(value, self.cash_ratio, price)
+    }
+}
+
+/// Constant product liquidity within [lower, upper] only, like a Uniswap v3 position. All
+/// coins below the range, all cash above it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Concentrated {
+    pub lower: f64,
+    pub upper: f64,
+    pub liquidity: f64,
+}
+
+impl Concentrated {
+    /// A range holding `value` at `price`.
+    pub fn new(value: f64, price: f64, lower: f64, upper: f64) -> Self {
+        assert!(0.0 < lower && lower < upper);
+        let unit = Self {
+            lower,
+            upper,
+            liquidity: 1.0,
+        };
+        Self {
+            liquidity: value / unit.value_at(price),
+            ..unit
+        }
+    }
+}
+
+impl BondingCurve for Concentrated {
+    fn reserves_at(&self, price: f64) -> (f64, f64) {
+        let price = price.max(self.lower).min(self.upper);
+        (
+            self.liquidity * (price.sqrt() - self.lower.sqrt()),
+            self.liquidity * (1.0 / price.sqrt() - 1.0 / self.upper.sqrt()),
+        )
+    }
+}
+
+/// Which moves a `CurveAMM` trades on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CurveMode {
+    /// Sell at new highs only, like `exit_insane_bull`.
+    SellOnRise,
+    /// Buy at new lows only, like `AMMBuyBear`.
+    BuyOnFall,
+    /// Follow the curve both ways.
+    Both,
+}
+
+/// Trades along a bonding curve, starting with the reserves of the curve at the start price.
+pub struct CurveAMM<C: BondingCurve> {
+    curve: C,
+    mode: CurveMode,
+    pub cash: f64,
+    pub coins: f64,
+    /// The highest and lowest price traded at.
+    high: f64,
+    low: f64,
+}
+
+impl<C: BondingCurve> CurveAMM<C> {
+    pub fn new(curve: C, price: f64, mode: CurveMode) -> Self {
+        let (cash, coins) = curve.reserves_at(price);
+        Self {
+            curve,
+            mode,
+            cash,
+            coins,
+            high: price,
+            low: price,
+        }
+    }
+
+    pub fn value(&self, price: f64) -> f64 {
+        self.cash + self.coins * price
+    }
+
+    /// Trades as the curve between the prices traded at before and `new_price`, logged as one
+    /// order at the average price.
+    /// Returns: (cash change, coins change).
+    pub fn update(&mut self, new_price: f64, logs: &mut BuyLogs) -> (f64, f64) {
+        let from_price = match self.mode {
+            CurveMode::SellOnRise if new_price > self.high => self.high,
+            CurveMode::BuyOnFall if new_price < self.low => self.low,
+            CurveMode::Both if new_price > self.high => self.high,
+            CurveMode::Both if new_price < self.low => self.low,
+            _ => return (0.0, 0.0),
+        };
+        match self.mode {
+            // Both ways, the curve is at the last price.
+            CurveMode::Both => {
+                self.high = new_price;
+                self.low = new_price;
+            }
+            _ => {
+                self.high = self.high.max(new_price);
+                self.low = self.low.min(new_price);
+            }
+        }
+
+        let (from_cash, from_coins) = self.curve.reserves_at(from_price);
+        let (to_cash, to_coins) = self.curve.reserves_at(new_price);
+        let (cash, coins) = (to_cash - from_cash, to_coins - from_coins);
+        if coins != 0.0 {
+            self.cash += cash;
+            self.coins += coins;
+            logs.push((-cash / coins, coins));
+        }
+
+        (cash, coins)
+    }
+
+    pub fn update_bar(
+        &mut self,
+        bar: &Row,
+        order: IntrabarOrder,
+        logs: &mut BuyLogs,
+    ) -> (f64, f64) {
+        let mut total = (0.0, 0.0);
+        for price in bar.intrabar_path(order) {
+            let (cash, coins) = self.update(price, logs);
+            total.0 += cash;
+            total.1 += coins;
+        }
+
+        total
+    }
+}
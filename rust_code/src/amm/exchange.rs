@@ -0,0 +1,113 @@
+use super::FeeModel;
+
+/// Order constraints of an exchange. A zero field means no constraint, the default accepts any
+/// order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeRules {
+    /// Prices are multiples of the tick.
+    pub price_tick: f64,
+    /// Quantities are multiples of the step.
+    pub quantity_step: f64,
+    /// Orders below this notional are rejected.
+    pub min_notional: f64,
+}
+
+impl ExchangeRules {
+    pub fn is_unrestricted(&self) -> bool {
+        self.price_tick <= 0.0 && self.quantity_step <= 0.0 && self.min_notional <= 0.0
+    }
+
+    /// Rounds down to a tick, so a buy never pays more than its level. Tolerates float error
+    /// just below a tick like `round_quantity`.
+    pub fn round_buy_price(&self, price: f64) -> f64 {
+        if self.price_tick > 0.0 {
+            (price / self.price_tick + 1e-9).floor() * self.price_tick
+        } else {
+            price
+        }
+    }
+
+    /// Rounds up to a tick, so a sell never gets less than its level.
+    pub fn round_sell_price(&self, price: f64) -> f64 {
+        if self.price_tick > 0.0 {
+            (price / self.price_tick - 1e-9).ceil() * self.price_tick
+        } else {
+            price
+        }
+    }
+
+    /// Rounds down to a multiple of the step, tolerating float error just below a multiple.
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        if self.quantity_step > 0.0 {
+            (quantity / self.quantity_step + 1e-9).floor() * self.quantity_step
+        } else {
+            quantity
+        }
+    }
+
+    /// The buy order that can be placed for at most `notional` cash at `price`.
+    /// Returns: (price, quantity, notional), or `None` when under the minimum notional.
+    pub fn round_buy(&self, price: f64, notional: f64) -> Option<(f64, f64, f64)> {
+        self.round_order(self.round_buy_price(price), price, notional)
+    }
+
+    /// The sell order that can be placed for about `notional` cash at `price`.
+    /// Returns: (price, quantity, notional), or `None` when under the minimum notional.
+    pub fn round_sell(&self, price: f64, notional: f64) -> Option<(f64, f64, f64)> {
+        self.round_order(self.round_sell_price(price), price, notional)
+    }
+
+    /// A resting buy at `price` for at most `cash`, the maker fee included.
+    /// Returns: (order price, coins bought, cash spent, fee), or `None` when nothing can be
+    /// placed.
+    pub fn fill_buy(
+        &self,
+        fee_model: &FeeModel,
+        price: f64,
+        cash: f64,
+    ) -> Option<(f64, f64, f64, f64)> {
+        let fee = fee_model.maker(cash);
+        if cash <= 0.0 || cash <= fee {
+            return None;
+        }
+        let (order_price, coins, notional) = self.round_buy(price, cash - fee)?;
+        let fee = fee_model.maker(notional);
+
+        Some((order_price, coins, notional + fee, fee))
+    }
+
+    /// A resting sell at `price` for about `cash` before the maker fee.
+    /// Returns: (order price, coins sold, cash received, fee), or `None` when nothing can be
+    /// placed or the fee takes it all.
+    pub fn fill_sell(
+        &self,
+        fee_model: &FeeModel,
+        price: f64,
+        cash: f64,
+    ) -> Option<(f64, f64, f64, f64)> {
+        if cash <= 0.0 {
+            return None;
+        }
+        let (order_price, coins, notional) = self.round_sell(price, cash)?;
+        let fee = fee_model.maker(notional);
+        if notional <= fee {
+            return None;
+        }
+
+        Some((order_price, coins, notional - fee, fee))
+    }
+
+    fn round_order(&self, order_price: f64, price: f64, notional: f64) -> Option<(f64, f64, f64)> {
+        if self.is_unrestricted() {
+            return Some((price, notional / price, notional));
+        }
+
+        let quantity = self.round_quantity(notional / price);
+        let order_notional = order_price * quantity;
+        if quantity <= 0.0 || order_notional < self.min_notional {
+            None
+        } else {
+            Some((order_price, quantity, order_notional))
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/// How ladder orders are filled when a bar moves over many levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionMode {
+    /// Resting limit orders, every level crossed fills at its own price.
+    #[default]
+    RestingLimit,
+    /// No resting orders, the AMM rebalances once per bar with a market order at the close.
+    MarketAtBar,
+    /// Resting limit orders, but the levels gapped over between the previous close and the open
+    /// fill at the open, like limit orders on an exchange. Bars without an open, e.g. daily
+    /// closes, gap to the close: every level crossed fills at the close.
+    GapAtOpen,
+}
+
+/// A ladder fill, `coins` are negative for sells like in `BuyLogs`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fill {
+    /// The ladder level the order is for.
+    pub level_price: f64,
+    pub fill_price: f64,
+    pub coins: f64,
+}
+
+impl Fill {
+    /// The cash gained compared to a fill at the level price.
+    pub fn price_improvement(&self) -> f64 {
+        (self.level_price - self.fill_price) * self.coins
+    }
+}
@@ -0,0 +1,16 @@
+/// When an `AMMBuyBear` stops and returns its cash and coins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ExpirationPolicy {
+    /// Expire at the tick given to `AMMBuyBear::new`.
+    #[default]
+    AtTick,
+    /// Expire once the price is this fraction above the start price, e.g. 0.2 for 20%.
+    PriceRise(f64),
+    /// Expire once the cash left is at most this fraction of the cash put.
+    CashExhausted(f64),
+    /// Release this fraction of the remaining cash every tick, and the rest at the expiration
+    /// tick.
+    Decay(f64),
+    /// Never expire, and never buy below this price.
+    NeverWithFloor(f64),
+}
@@ -0,0 +1,34 @@
+/// Trading costs. Ladder fills are resting limit orders and pay the maker fee at their limit
+/// price; dollar cost average purchases are market orders and pay the taker fee plus half the
+/// spread. The default model is free.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeModel {
+    /// Fraction of the notional, e.g. 0.001 for 0.1%.
+    pub maker_fee: f64,
+    /// Fraction of the notional.
+    pub taker_fee: f64,
+    /// Cash charged on every order.
+    pub fixed_fee: f64,
+    /// Relative bid/ask spread around the quoted price.
+    pub spread: f64,
+}
+
+impl FeeModel {
+    pub fn maker(&self, notional: f64) -> f64 {
+        notional * self.maker_fee + self.fixed_fee
+    }
+
+    pub fn taker(&self, notional: f64) -> f64 {
+        notional * self.taker_fee + self.fixed_fee
+    }
+
+    /// The ask a market buy pays when `price` is the mid price.
+    pub fn market_buy_price(&self, price: f64) -> f64 {
+        price * (1.0 + self.spread / 2.0)
+    }
+
+    /// The bid a market sell gets when `price` is the mid price.
+    pub fn market_sell_price(&self, price: f64) -> f64 {
+        price * (1.0 - self.spread / 2.0)
+    }
+}
@@ -0,0 +1,161 @@
+use super::*;
+
+/// A two-sided AMM keeping the cash ratio on a fixed grid of levels `anchor * (1 + step)^i`: it
+/// sells coins at every level crossed going up and buys at every level crossed going down. The
+/// holdings are rebalanced at the first level crossed.
+///
+/// Fills are matched against the opposite fills before them, last in first out, e.g. a buy at
+/// 10000 followed by a sell at 10050 realizes `grid_profit` of 50 per coin. The value change of
+/// the AMM is `directional_pnl + grid_profit + open_pnl - fees_paid`.
+pub struct AMMGrid {
+    anchor_price: f64,
+    level: i32,
+    pub cash: f64,
+    pub coins: f64,
+    pub fees_paid: f64,
+    pub grid_profit: f64,
+    start_coins: f64,
+    /// Unmatched fills: (price, coins), coins are negative for sells.
+    lots: BuyLogs,
+
+    rebalance_cash_ratio: f64,
+    rebalance_step_percentage: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+}
+
+impl AMMGrid {
+    pub fn new(
+        cash: f64,
+        coins: f64,
+        price: f64,
+        rebalance_cash_ratio: f64,
+        rebalance_step_percentage: f64,
+    ) -> Self {
+        Self {
+            anchor_price: price,
+            level: 0,
+            cash,
+            coins,
+            fees_paid: 0.0,
+            grid_profit: 0.0,
+            start_coins: coins,
+            lots: vec![],
+            rebalance_cash_ratio,
+            rebalance_step_percentage,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
+        }
+    }
+
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+    }
+
+    pub fn level_price(&self, level: i32) -> f64 {
+        self.anchor_price * (1.0 + self.rebalance_step_percentage).powi(level)
+    }
+
+    /// The price of the last level crossed.
+    pub fn last_price(&self) -> f64 {
+        self.level_price(self.level)
+    }
+
+    pub fn value(&self, price: f64) -> f64 {
+        self.cash + self.coins * price
+    }
+
+    /// The PnL at `price` of holding the starting coins without trading.
+    pub fn directional_pnl(&self, price: f64) -> f64 {
+        self.start_coins * (price - self.anchor_price)
+    }
+
+    /// The PnL at `price` of the fills not matched yet.
+    pub fn open_pnl(&self, price: f64) -> f64 {
+        self.lots.iter().map(|lot| lot.1 * (price - lot.0)).sum()
+    }
+
+    /// Rebalance at every level between the last level and `new_price`.
+    /// Returns: (cash change, coins change) after fees.
+    pub fn update(&mut self, new_price: f64, logs: &mut BuyLogs) -> (f64, f64) {
+        let (cash, coins) = (self.cash, self.coins);
+        while self.level_price(self.level + 1) <= new_price {
+            self.level += 1;
+            self.rebalance(self.level_price(self.level), logs);
+        }
+        while self.level_price(self.level - 1) >= new_price {
+            self.level -= 1;
+            self.rebalance(self.level_price(self.level), logs);
+        }
+
+        (self.cash - cash, self.coins - coins)
+    }
+
+    /// Update along the intrabar path of the bar, so levels touched by the high and the low are
+    /// filled.
+    pub fn update_bar(
+        &mut self,
+        bar: &Row,
+        order: IntrabarOrder,
+        logs: &mut BuyLogs,
+    ) -> (f64, f64) {
+        let mut total = (0.0, 0.0);
+        for price in bar.intrabar_path(order) {
+            let (cash, coins) = self.update(price, logs);
+            total.0 += cash;
+            total.1 += coins;
+        }
+
+        total
+    }
+
+    fn rebalance(&mut self, price: f64, logs: &mut BuyLogs) {
+        let total = self.cash + self.coins * price;
+        let take_out = total * self.rebalance_cash_ratio - self.cash;
+
+        if let Some((order_price, coins_to_sell, received, fee)) =
+            self.exchange_rules
+                .fill_sell(&self.fee_model, price, take_out)
+        {
+            self.cash += received;
+            self.fees_paid += fee;
+            self.coins -= coins_to_sell;
+            self.match_lots(order_price, -coins_to_sell);
+            logs.push((order_price, -coins_to_sell));
+        } else if let Some((order_price, coins_to_buy, spent, fee)) =
+            self.exchange_rules
+                .fill_buy(&self.fee_model, price, -take_out)
+        {
+            self.cash -= spent;
+            self.fees_paid += fee;
+            self.coins += coins_to_buy;
+            self.match_lots(order_price, coins_to_buy);
+            logs.push((order_price, coins_to_buy));
+        }
+    }
+
+    fn match_lots(&mut self, price: f64, mut coins: f64) {
+        while coins != 0.0 {
+            match self.lots.last_mut() {
+                Some(lot) if lot.1 * coins < 0.0 => {
+                    let matched = lot.1.abs().min(coins.abs());
+                    // A sell matched with an earlier buy, or a buy with an earlier sell.
+                    self.grid_profit += matched * (price - lot.0) * lot.1.signum();
+                    lot.1 -= matched * lot.1.signum();
+                    coins -= matched * coins.signum();
+                    if lot.1 == 0.0 {
+                        self.lots.pop();
+                    }
+                }
+                _ => {
+                    self.lots.push((price, coins));
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,30 @@
 use super::*;
 
+pub mod cash_ratio;
+pub mod closed_form;
+pub mod curve;
+pub mod exchange;
+pub mod execution;
+pub mod expiration;
+pub mod fees;
+pub mod grid;
+pub mod planner;
+pub mod range_position;
+pub mod reentry;
+
+pub use self::cash_ratio::{CashRatioPath, CashRatioPathError, PricePoints};
+pub use self::curve::{
+    BondingCurve, Concentrated, ConstantProduct, ConstantRatio, CurveAMM, CurveMode,
+};
+pub use self::exchange::ExchangeRules;
+pub use self::execution::{ExecutionMode, Fill};
+pub use self::expiration::ExpirationPolicy;
+pub use self::fees::FeeModel;
+pub use self::grid::AMMGrid;
+pub use self::planner::{LadderPlanner, LimitOrder};
+pub use self::range_position::{RangePosition, RangeReport};
+pub use self::reentry::{AMMReentry, ReentryReport};
+
 // rebalance_percent_steps: 1%: rebalance for each 1% change of the price.
 // finish_price: only sell when start_price is lower than finish price.
 // sell_log: a list of (price, amount) to sell.
@@ -12,42 +37,400 @@ pub fn exit_insane_bull(
     finish_price: f64,
     sell_log: &mut BuyLogs,
 ) {
-    if finish_price <= *start_price {
-        return;
+    ExitLadder::new(cash_ratio, rebalance_percent_steps).exit(
+        start_cash,
+        start_coins,
+        start_price,
+        finish_price,
+        sell_log,
+    );
+}
+
+/// The ladder of `exit_insane_bull` with its trading settings, which are free by default.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExitLadder {
+    cash_ratio: f64,
+    /// Replaces `cash_ratio` when set. Not saved, as `CashRatioPath::Custom` can not be.
+    #[serde(skip)]
+    cash_ratio_path: Option<CashRatioPath>,
+    rebalance_percent_steps: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+    #[serde(default)]
+    execution_mode: ExecutionMode,
+    fast_path: bool,
+}
+
+impl ExitLadder {
+    pub fn new(cash_ratio: f64, rebalance_percent_steps: f64) -> Self {
+        Self {
+            cash_ratio,
+            cash_ratio_path: None,
+            rebalance_percent_steps,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
+            execution_mode: ExecutionMode::default(),
+            fast_path: false,
+        }
+    }
+
+    /// A cash ratio depending on the price instead of the fixed one, e.g. a glide path selling
+    /// more as the price gets higher.
+    pub fn set_cash_ratio_path(&mut self, cash_ratio_path: CashRatioPath) {
+        self.cash_ratio_path = Some(cash_ratio_path);
+    }
+
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    /// Sell with `AMMSellBull::sell_fast`. The orders are not logged then.
+    pub fn set_fast_path(&mut self, fast_path: bool) {
+        self.fast_path = fast_path;
+    }
+
+    /// See `AMMSellBull::set_exchange_rules`.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+    }
+
+    /// How `exit_bar` and the AMM of `ladder` fill the orders.
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        self.execution_mode = execution_mode;
+    }
+
+    /// The AMM of this ladder with `cash` and `coins` at `price`, e.g. to feed a
+    /// `stream::LadderDriver`.
+    pub fn ladder(&self, cash: f64, coins: f64, price: f64) -> AMMSellBull {
+        let mut amm = AMMSellBull::new(
+            cash,
+            coins,
+            price,
+            self.cash_ratio,
+            self.rebalance_percent_steps,
+        );
+        if let Some(cash_ratio_path) = &self.cash_ratio_path {
+            amm.set_cash_ratio_path(cash_ratio_path.clone());
+        }
+        amm.set_fee_model(self.fee_model);
+        amm.set_exchange_rules(self.exchange_rules);
+        amm.set_execution_mode(self.execution_mode);
+
+        amm
+    }
+
+    /// `exit` over a bar with the execution mode, see `AMMSellBull::execute_bar`. The fast path
+    /// does not apply.
+    /// Returns: the fees paid.
+    pub fn exit_bar(
+        &self,
+        start_cash: &mut f64,
+        start_coins: &mut f64,
+        start_price: &mut f64,
+        bar: &Row,
+        order: IntrabarOrder,
+        fills: &mut Vec<Fill>,
+    ) -> f64 {
+        let mut amm = self.ladder(*start_cash, *start_coins, *start_price);
+        amm.execute_bar(bar, order, fills);
+
+        *start_cash = amm.cash;
+        *start_price = amm.last_price;
+        *start_coins = amm.coins;
+
+        amm.fees_paid
+    }
+
+    /// `exit_insane_bull` on this ladder.
+    /// Returns: the fees paid.
+    pub fn exit(
+        &self,
+        start_cash: &mut f64,
+        start_coins: &mut f64,
+        start_price: &mut f64,
+        finish_price: f64,
+        sell_log: &mut BuyLogs,
+    ) -> f64 {
+        let mut amm = self.ladder(*start_cash, *start_coins, *start_price);
+        if self.fast_path {
+            amm.sell_fast(finish_price);
+        } else {
+            amm.sell(finish_price, sell_log);
+        }
+
+        *start_cash = amm.cash;
+        *start_price = amm.last_price;
+        *start_coins = amm.coins;
+
+        amm.fees_paid
+    }
+}
+
+/// The AMM behind `exit_insane_bull`: sells coins to keep the cash ratio while the price goes up.
+pub struct AMMSellBull {
+    /// The next price to rebalance at.
+    pub last_price: f64,
+    pub cash: f64,
+    pub coins: f64,
+    pub fees_paid: f64,
+
+    cash_ratio_path: CashRatioPath,
+    rebalance_step_percentage: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+    execution_mode: ExecutionMode,
+}
+
+impl AMMSellBull {
+    pub fn new(
+        cash: f64,
+        coins: f64,
+        price: f64,
+        rebalance_cash_ratio: f64,
+        rebalance_step_percentage: f64,
+    ) -> Self {
+        Self {
+            last_price: price,
+            cash,
+            coins,
+            fees_paid: 0.0,
+            cash_ratio_path: CashRatioPath::Fixed(rebalance_cash_ratio),
+            rebalance_step_percentage,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
+            execution_mode: ExecutionMode::default(),
+        }
+    }
+
+    /// Replaces the fixed cash ratio of `new`.
+    pub fn set_cash_ratio_path(&mut self, cash_ratio_path: CashRatioPath) {
+        self.cash_ratio_path = cash_ratio_path;
+    }
+
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    /// Orders are rounded to the rules. What can not be placed stays in the AMM and is included
+    /// in the rebalance of the next level.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+    }
+
+    /// Only sell when price goes up.
+    /// Returns: (cash taken out after fees, coins sold).
+    pub fn sell(&mut self, new_price: f64, sell_log: &mut BuyLogs) -> (f64, f64) {
+        if new_price <= self.last_price {
+            return (0.0, 0.0);
+        }
+
+        let mut price = self.last_price;
+        let mut cash_taken_out = 0.0;
+        let mut coins_sold = 0.0;
+        while price < new_price {
+            let total = self.cash + self.coins * price;
+            let take_out = total * self.cash_ratio_path.ratio_at(price) - self.cash;
+
+            if let Some((order_price, coins_to_sell, received, fee)) = self
+                .exchange_rules
+                .fill_sell(&self.fee_model, price, take_out)
+            {
+                self.cash += received;
+                self.fees_paid += fee;
+                self.coins -= coins_to_sell;
+                cash_taken_out += received;
+                coins_sold += coins_to_sell;
+
+                sell_log.push((order_price, -coins_to_sell));
+            }
+
+            price += price * self.rebalance_step_percentage;
+        }
+        self.last_price = price;
+
+        (cash_taken_out, coins_sold)
     }
 
-    let mut price: f64 = *start_price;
-    let mut cash: f64 = *start_cash as f64;
-    let mut coins: f64 = *start_coins as f64;
+    /// How `execute_bar` fills the orders.
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        self.execution_mode = execution_mode;
+    }
 
-    while price < finish_price {
-        let total = cash + coins * price;
-        let take_out = total * cash_ratio - cash;
+    /// Sell for a bar with the execution mode. `fills` gets the level and the fill price of
+    /// every order. Bars without an open gap from the previous close to the close.
+    /// Returns: (cash taken out after fees, coins sold).
+    pub fn execute_bar(
+        &mut self,
+        bar: &Row,
+        order: IntrabarOrder,
+        fills: &mut Vec<Fill>,
+    ) -> (f64, f64) {
+        let mut sell_log = vec![];
+        match self.execution_mode {
+            ExecutionMode::RestingLimit => {
+                let sold = self.sell_bar(bar, order, &mut sell_log);
+                fills.extend(sell_log.iter().map(|log| Fill {
+                    level_price: log.0,
+                    fill_price: log.0,
+                    coins: log.1,
+                }));
+                sold
+            }
+            ExecutionMode::MarketAtBar => self.market_sell(bar.price, fills),
+            ExecutionMode::GapAtOpen => {
+                let open = bar.open.unwrap_or(bar.price);
+                let (mut cash, coins) = self.sell(open, &mut sell_log);
+                for log in sell_log.drain(..) {
+                    // The order for the level fills at the open instead.
+                    let fee =
+                        self.fee_model.maker(-log.1 * open) - self.fee_model.maker(-log.1 * log.0);
+                    let improvement = -log.1 * (open - log.0) - fee;
+                    self.cash += improvement;
+                    self.fees_paid += fee;
+                    cash += improvement;
+                    fills.push(Fill {
+                        level_price: log.0,
+                        fill_price: open,
+                        coins: log.1,
+                    });
+                }
 
-        if take_out > 0.0 {
-            cash += take_out;
-            let coins_to_sell = take_out / price;
-            coins -= coins_to_sell;
+                let mut total = (cash, coins);
+                for price in bar.intrabar_path(order) {
+                    let (cash, coins) = self.sell(price, &mut sell_log);
+                    total.0 += cash;
+                    total.1 += coins;
+                }
+                fills.extend(sell_log.iter().map(|log| Fill {
+                    level_price: log.0,
+                    fill_price: log.0,
+                    coins: log.1,
+                }));
+                total
+            }
+        }
+    }
 
-            sell_log.push((price, -coins_to_sell));
+    /// Rebalance with one market order at the bid if `price` crossed a level. The fill is
+    /// logged at the highest level crossed.
+    fn market_sell(&mut self, price: f64, fills: &mut Vec<Fill>) -> (f64, f64) {
+        if price <= self.last_price {
+            return (0.0, 0.0);
+        }
+        let mut level_price = self.last_price;
+        while level_price < price {
+            self.last_price = level_price;
+            level_price += level_price * self.rebalance_step_percentage;
         }
+        let level = self.last_price;
+        self.last_price = level_price;
 
-        price += price * rebalance_percent_steps;
+        let bid = self.fee_model.market_sell_price(price);
+        let total = self.cash + self.coins * bid;
+        let take_out = total * self.cash_ratio_path.ratio_at(price) - self.cash;
+        if take_out <= 0.0 {
+            return (0.0, 0.0);
+        }
+        match self.exchange_rules.round_sell(bid, take_out) {
+            Some((fill_price, coins_to_sell, notional)) => {
+                let fee = self.fee_model.taker(notional);
+                if notional <= fee {
+                    return (0.0, 0.0);
+                }
+                self.cash += notional - fee;
+                self.fees_paid += fee;
+                self.coins -= coins_to_sell;
+                fills.push(Fill {
+                    level_price: level,
+                    fill_price,
+                    coins: -coins_to_sell,
+                });
+                (notional - fee, coins_to_sell)
+            }
+            None => (0.0, 0.0),
+        }
     }
 
-    *start_cash = cash;
-    *start_price = price;
-    *start_coins = coins;
+    /// `sell` in closed form, see `closed_form`, without logging the orders. Falls back to `sell`
+    /// when there are fees, exchange rules or a cash ratio path.
+    pub fn sell_fast(&mut self, new_price: f64) -> (f64, f64) {
+        let ratio = match self.cash_ratio_path {
+            CashRatioPath::Fixed(ratio) => ratio,
+            _ => return self.sell(new_price, &mut vec![]),
+        };
+        if self.fee_model.maker(1.0) != 0.0 || !self.exchange_rules.is_unrestricted() {
+            return self.sell(new_price, &mut vec![]);
+        }
+
+        let step = self.rebalance_step_percentage;
+        let levels = closed_form::levels_up(self.last_price, new_price, step);
+        if levels == 0 {
+            return (0.0, 0.0);
+        }
+        let start_price = self.last_price;
+        self.last_price = start_price * (1.0 + step).powi(levels as i32);
+
+        // The first level with less cash than the ratio, the AMM stays rebalanced from there.
+        let first = if (self.cash + self.coins * start_price) * ratio > self.cash {
+            0
+        } else if self.coins > 0.0 && ratio > 0.0 {
+            let threshold = self.cash * (1.0 - ratio) / (ratio * self.coins);
+            closed_form::levels_up(start_price, threshold, step)
+        } else {
+            levels
+        };
+        if first >= levels {
+            return (0.0, 0.0);
+        }
+
+        let first_price = start_price * (1.0 + step).powi(first as i32);
+        let k = levels - 1 - first;
+        let value =
+            closed_form::value_after_steps_up(self.cash + self.coins * first_price, ratio, step, k);
+        let (cash, coins) =
+            closed_form::rebalanced(value, ratio, first_price * (1.0 + step).powi(k as i32));
+        let sold = (cash - self.cash, self.coins - coins);
+        self.cash = cash;
+        self.coins = coins;
+
+        sold
+    }
+
+    /// Sell along the intrabar path of the bar, so levels touched by the high are filled.
+    pub fn sell_bar(
+        &mut self,
+        bar: &Row,
+        order: IntrabarOrder,
+        sell_log: &mut BuyLogs,
+    ) -> (f64, f64) {
+        let mut total = (0.0, 0.0);
+        for price in bar.intrabar_path(order) {
+            let (cash, coins) = self.sell(price, sell_log);
+            total.0 += cash;
+            total.1 += coins;
+        }
+
+        total
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct AMMBuyBear {
     pub last_price: f64,
     tick_to_expire: usize,
+    start_price: f64,
+    start_cash: f64,
+    #[serde(default)]
+    cash_released: f64,
+    expiration_policy: ExpirationPolicy,
     pub cash: f64,
     pub coins: f64,
+    pub fees_paid: f64,
 
     rebalance_cash_ratio: f64,
     rebalance_step_percentage: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
 }
 
 impl AMMBuyBear {
@@ -62,15 +445,86 @@ impl AMMBuyBear {
             last_price: price,
             cash,
             tick_to_expire,
+            start_price: price,
+            start_cash: cash,
+            cash_released: 0.0,
+            expiration_policy: ExpirationPolicy::default(),
             coins: 0.0,
+            fees_paid: 0.0,
             rebalance_cash_ratio,
             rebalance_step_percentage,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
         }
     }
 
-    pub fn check_expiration(&mut self, tick: usize) -> (bool, f64, f64) {
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    /// Orders are rounded to the rules. What can not be placed stays in the AMM and is included
+    /// in the rebalance of the next level.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+    }
+
+    pub fn set_expiration_policy(&mut self, expiration_policy: ExpirationPolicy) {
+        self.expiration_policy = expiration_policy;
+    }
+
+    pub fn expiration_policy(&self) -> ExpirationPolicy {
+        self.expiration_policy
+    }
+
+    /// The tick of the expiration with `ExpirationPolicy::AtTick` and `Decay`.
+    pub fn tick_to_expire(&self) -> usize {
+        self.tick_to_expire
+    }
+
+    /// The cash the AMM was put with.
+    pub fn start_cash(&self) -> f64 {
+        self.start_cash
+    }
+
+    /// The cash taken out before the expiration, see `release_cash`.
+    pub fn cash_released(&self) -> f64 {
+        self.cash_released
+    }
+
+    /// Whether the cash left is too little for any order under the exchange rules.
+    pub fn is_spent(&self) -> bool {
+        self.cash <= 0.0 || self.cash < self.exchange_rules.min_notional
+    }
+
+    /// Takes up to `cash` out of the cash not invested yet.
+    /// Returns: the cash taken out.
+    pub fn release_cash(&mut self, cash: f64) -> f64 {
+        let released = cash.min(self.cash).max(0.0);
+        self.cash -= released;
+        self.cash_released += released;
+        released
+    }
+
+    /// Checks the expiration policy at `tick` with the current `price`.
+    /// Returns: (expired, cash taken out, coins taken out). Cash can be taken out before the
+    /// expiration with `ExpirationPolicy::Decay`.
+    pub fn check_expiration(&mut self, tick: usize, price: f64) -> (bool, f64, f64) {
+        let expire = match self.expiration_policy {
+            ExpirationPolicy::AtTick => tick >= self.tick_to_expire,
+            ExpirationPolicy::PriceRise(rise) => price >= self.start_price * (1.0 + rise),
+            ExpirationPolicy::CashExhausted(fraction) => self.cash <= self.start_cash * fraction,
+            ExpirationPolicy::Decay(fraction) => {
+                if tick < self.tick_to_expire {
+                    let released = self.release_cash(self.cash * fraction);
+                    return (false, released, 0.0);
+                }
+                true
+            }
+            ExpirationPolicy::NeverWithFloor(_) => false,
+        };
+
         let take_out;
-        if tick >= self.tick_to_expire {
+        if expire {
             take_out = (true, self.cash, self.coins);
             self.cash = 0.0;
             self.coins = 0.0;
@@ -82,8 +536,12 @@ impl AMMBuyBear {
     }
 
     /// Only buy when price goes down.
-    /// Returns: (cash spent, coins bought).
+    /// Returns: (cash spent including fees, coins bought).
     pub fn buy(&mut self, new_price: f64, buy_logs: &mut BuyLogs) -> (f64, f64) {
+        let new_price = match self.expiration_policy {
+            ExpirationPolicy::NeverWithFloor(floor) => new_price.max(floor),
+            _ => new_price,
+        };
         let mut price = self.last_price;
         if new_price > price {
             return (0.0, 0.0);
@@ -96,14 +554,16 @@ impl AMMBuyBear {
             let total = self.cash + self.coins * price;
             let buy = self.cash - total * self.rebalance_cash_ratio;
 
-            if buy > 0.0 {
-                let coins_to_buy = buy / price;
-                self.cash -= buy;
+            if let Some((order_price, coins_to_buy, spent, fee)) =
+                self.exchange_rules.fill_buy(&self.fee_model, price, buy)
+            {
+                self.cash -= spent;
+                self.fees_paid += fee;
                 self.coins += coins_to_buy;
-                cash_invested += buy;
+                cash_invested += spent;
                 coins_invested += coins_to_buy;
 
-                buy_logs.push((price, coins_to_buy));
+                buy_logs.push((order_price, coins_to_buy));
             }
 
             price -= price * self.rebalance_step_percentage;
@@ -112,4 +572,71 @@ impl AMMBuyBear {
 
         (cash_invested, coins_invested)
     }
+
+    /// `buy` in closed form, see `closed_form`, without logging the orders. Falls back to `buy`
+    /// when there are fees or exchange rules.
+    pub fn buy_fast(&mut self, new_price: f64) -> (f64, f64) {
+        let new_price = match self.expiration_policy {
+            ExpirationPolicy::NeverWithFloor(floor) => new_price.max(floor),
+            _ => new_price,
+        };
+        if self.fee_model.maker(1.0) != 0.0 || !self.exchange_rules.is_unrestricted() {
+            return self.buy(new_price, &mut vec![]);
+        }
+
+        let ratio = self.rebalance_cash_ratio;
+        let step = self.rebalance_step_percentage;
+        let levels = closed_form::levels_down(self.last_price, new_price, step);
+        if levels == 0 {
+            return (0.0, 0.0);
+        }
+        let start_price = self.last_price;
+        self.last_price = start_price * (1.0 - step).powi(levels as i32);
+
+        // The first level with more cash than the ratio, the AMM stays rebalanced from there.
+        let first = if self.cash > (self.cash + self.coins * start_price) * ratio {
+            0
+        } else if self.coins > 0.0 && ratio > 0.0 {
+            let threshold = self.cash * (1.0 - ratio) / (ratio * self.coins);
+            closed_form::levels_down(start_price, threshold, step)
+        } else {
+            levels
+        };
+        if first >= levels {
+            return (0.0, 0.0);
+        }
+
+        let first_price = start_price * (1.0 - step).powi(first as i32);
+        let k = levels - 1 - first;
+        let value = closed_form::value_after_steps_down(
+            self.cash + self.coins * first_price,
+            ratio,
+            step,
+            k,
+        );
+        let (cash, coins) =
+            closed_form::rebalanced(value, ratio, first_price * (1.0 - step).powi(k as i32));
+        let bought = (self.cash - cash, coins - self.coins);
+        self.cash = cash;
+        self.coins = coins;
+
+        bought
+    }
+
+    /// Buy along the intrabar path of the bar, so levels touched by the low are filled.
+    pub fn buy_bar(
+        &mut self,
+        bar: &Row,
+        order: IntrabarOrder,
+        buy_logs: &mut BuyLogs,
+    ) -> (f64, f64) {
+        let mut total = (0.0, 0.0);
+        for price in bar.intrabar_path(order) {
+            let (cash, coins) = self.buy(price, buy_logs);
+            total.0 += cash;
+            total.1 += coins;
+        }
+
+        total
+    }
 }
@@ -0,0 +1,89 @@
+use super::*;
+
+/// A limit order to submit up front. `quantity` is in coins and always positive.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LimitOrder {
+    pub price: f64,
+    pub quantity: f64,
+    pub notional: f64,
+}
+
+impl LimitOrder {
+    fn from_log(log: &(f64, f64)) -> Self {
+        let quantity = log.1.abs();
+        Self {
+            price: log.0,
+            quantity,
+            notional: log.0 * quantity,
+        }
+    }
+}
+
+/// Lists the orders of the AMM ladders for the current holdings, without a price history, so
+/// they can be placed on an exchange at once. The orders are those the AMMs would fill if the
+/// price moved straight through the range.
+pub struct LadderPlanner {
+    cash: f64,
+    coins: f64,
+    cash_ratio: f64,
+    step_percentage: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+}
+
+impl LadderPlanner {
+    pub fn new(cash: f64, coins: f64, cash_ratio: f64, step_percentage: f64) -> Self {
+        Self {
+            cash,
+            coins,
+            cash_ratio,
+            step_percentage,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
+        }
+    }
+
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+    }
+
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+    }
+
+    /// The sell orders of `exit_insane_bull` at the levels from `from_price` up to below
+    /// `to_price`. The first order restores the cash ratio at `from_price` if needed.
+    pub fn sell_orders(&self, from_price: f64, to_price: f64) -> Vec<LimitOrder> {
+        let mut amm = AMMSellBull::new(
+            self.cash,
+            self.coins,
+            from_price,
+            self.cash_ratio,
+            self.step_percentage,
+        );
+        amm.set_fee_model(self.fee_model);
+        amm.set_exchange_rules(self.exchange_rules);
+
+        let mut sell_log = vec![];
+        amm.sell(to_price, &mut sell_log);
+        sell_log.iter().map(LimitOrder::from_log).collect()
+    }
+
+    /// The buy orders of `AMMBuyBear` at the levels from `from_price` down to `to_price`.
+    pub fn buy_orders(&self, from_price: f64, to_price: f64) -> Vec<LimitOrder> {
+        let mut amm = AMMBuyBear::new(
+            self.cash,
+            from_price,
+            usize::MAX,
+            self.cash_ratio,
+            self.step_percentage,
+        );
+        amm.coins = self.coins;
+        amm.set_fee_model(self.fee_model);
+        amm.set_exchange_rules(self.exchange_rules);
+
+        let mut buy_logs = vec![];
+        amm.buy(to_price, &mut buy_logs);
+        buy_logs.iter().map(LimitOrder::from_log).collect()
+    }
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+/// The state of a `RangePosition` after a bar.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RangeReport {
+    pub date: String,
+    pub price: f64,
+    pub in_range: bool,
+    /// Reserves of the position, without the fees.
+    pub cash: f64,
+    pub coins: f64,
+    pub value: f64,
+    /// The value of the starting cash and coins held instead.
+    pub hold_value: f64,
+    /// `value / hold_value - 1`, zero or negative.
+    pub impermanent_loss: f64,
+    /// Fees earned so far, collected as cash.
+    pub fees_earned: f64,
+}
+
+/// A liquidity position within a price range on a Uniswap v3 style DEX, see `Concentrated`.
+/// While the close of a bar is in range, the position earns
+/// `volume * price * fee_tier * volume_share` of the bar, `volume` in coins.
+pub struct RangePosition {
+    curve: Concentrated,
+    fee_tier: f64,
+    volume_share: f64,
+    assumed_volume: f64,
+    start_cash: f64,
+    start_coins: f64,
+    pub fees_earned: f64,
+}
+
+impl RangePosition {
+    /// A position worth `value` at `price` in [lower, upper], e.g. fee tier 0.003 for 0.3%, and
+    /// `volume_share` the fraction of the pool volume trading against this position.
+    pub fn new(
+        value: f64,
+        price: f64,
+        lower: f64,
+        upper: f64,
+        fee_tier: f64,
+        volume_share: f64,
+    ) -> Self {
+        let curve = Concentrated::new(value, price, lower, upper);
+        let (start_cash, start_coins) = curve.reserves_at(price);
+        Self {
+            curve,
+            fee_tier,
+            volume_share,
+            assumed_volume: 0.0,
+            start_cash,
+            start_coins,
+            fees_earned: 0.0,
+        }
+    }
+
+    /// The volume in coins of bars without one.
+    pub fn set_assumed_volume(&mut self, volume: f64) {
+        self.assumed_volume = volume;
+    }
+
+    pub fn in_range(&self, price: f64) -> bool {
+        self.curve.lower <= price && price <= self.curve.upper
+    }
+
+    pub fn push(&mut self, bar: &Row) -> RangeReport {
+        let price = bar.price;
+        let in_range = self.in_range(price);
+        if in_range {
+            let volume = bar.volume.unwrap_or(self.assumed_volume);
+            self.fees_earned += volume * price * self.fee_tier * self.volume_share;
+        }
+
+        let (cash, coins) = self.curve.reserves_at(price);
+        let value = cash + coins * price;
+        let hold_value = self.start_cash + self.start_coins * price;
+        RangeReport {
+            date: bar.date.clone(),
+            price,
+            in_range,
+            cash,
+            coins,
+            value,
+            hold_value,
+            impermanent_loss: value / hold_value - 1.0,
+            fees_earned: self.fees_earned,
+        }
+    }
+
+    pub fn simulate(&mut self, rows: &[Row]) -> Vec<RangeReport> {
+        rows.iter().map(|row| self.push(row)).collect()
+    }
+}
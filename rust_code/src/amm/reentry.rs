@@ -0,0 +1,150 @@
+use super::*;
+
+/// How much of the coins sold by the exit ladder were bought back.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ReentryReport {
+    pub coins_sold: f64,
+    pub coins_bought_back: f64,
+    /// Including the fees of the buys.
+    pub cash_spent: f64,
+    /// The fees of the sells and the buys.
+    pub fees_paid: f64,
+    /// `coins_bought_back / coins_sold`.
+    pub recovered: f64,
+}
+
+/// The bull exit of `AMMSellBull` with a re-entry ladder. Once the price falls `pullback` below
+/// its peak, coins are bought back at every step down, with a target cash ratio going from the
+/// ratio at the trigger to the starting ratio `depth` below the trigger. A new peak resumes the
+/// exit ladder.
+pub struct AMMReentry {
+    exit: AMMSellBull,
+    start_cash_ratio: f64,
+    step_percentage: f64,
+    pullback: f64,
+    depth: f64,
+    peak: f64,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+    /// The next level and the target cash ratios of the re-entry ladder, while re-entering.
+    reentry: Option<(f64, CashRatioPath)>,
+
+    coins_sold: f64,
+    coins_bought_back: f64,
+    cash_spent: f64,
+}
+
+impl AMMReentry {
+    pub fn new(
+        cash: f64,
+        coins: f64,
+        price: f64,
+        cash_ratio_path: CashRatioPath,
+        step_percentage: f64,
+        pullback: f64,
+        depth: f64,
+    ) -> Self {
+        let mut exit = AMMSellBull::new(
+            cash,
+            coins,
+            price,
+            cash_ratio_path.ratio_at(price),
+            step_percentage,
+        );
+        exit.set_cash_ratio_path(cash_ratio_path);
+        Self {
+            exit,
+            start_cash_ratio: cash / (cash + coins * price),
+            step_percentage,
+            pullback,
+            depth,
+            peak: price,
+            fee_model: FeeModel::default(),
+            exchange_rules: ExchangeRules::default(),
+            reentry: None,
+            coins_sold: 0.0,
+            coins_bought_back: 0.0,
+            cash_spent: 0.0,
+        }
+    }
+
+    /// For the sells and the buys.
+    pub fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.fee_model = fee_model;
+        self.exit.set_fee_model(fee_model);
+    }
+
+    /// For the sells and the buys, see `AMMSellBull::set_exchange_rules`.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.exchange_rules = exchange_rules;
+        self.exit.set_exchange_rules(exchange_rules);
+    }
+
+    pub fn cash(&self) -> f64 {
+        self.exit.cash
+    }
+
+    pub fn coins(&self) -> f64 {
+        self.exit.coins
+    }
+
+    pub fn is_reentering(&self) -> bool {
+        self.reentry.is_some()
+    }
+
+    pub fn report(&self) -> ReentryReport {
+        ReentryReport {
+            coins_sold: self.coins_sold,
+            coins_bought_back: self.coins_bought_back,
+            cash_spent: self.cash_spent,
+            fees_paid: self.exit.fees_paid,
+            recovered: if self.coins_sold > 0.0 {
+                self.coins_bought_back / self.coins_sold
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Sells on new peaks and buys back after a pullback. `logs` gets both, sells with negative
+    /// coins.
+    pub fn update(&mut self, price: f64, logs: &mut BuyLogs) {
+        if price > self.peak {
+            self.peak = price;
+            self.reentry = None;
+            let (_cash, coins) = self.exit.sell(price, logs);
+            self.coins_sold += coins;
+            return;
+        }
+
+        if self.reentry.is_none() && price <= self.peak * (1.0 - self.pullback) {
+            let trigger = self.peak * (1.0 - self.pullback);
+            let total = self.exit.cash + self.exit.coins * trigger;
+            // Sorted by price unless the depth is negative, then there is no re-entry ladder.
+            self.reentry = CashRatioPath::piecewise_linear(vec![
+                (trigger * (1.0 - self.depth), self.start_cash_ratio),
+                (trigger, self.exit.cash / total),
+            ])
+            .ok()
+            .map(|targets| (trigger, targets));
+        }
+
+        if let Some((level, targets)) = self.reentry.as_mut() {
+            while *level >= price {
+                let total = self.exit.cash + self.exit.coins * *level;
+                let buy = self.exit.cash - total * targets.ratio_at(*level);
+                if let Some((order_price, coins, spent, fee)) =
+                    self.exchange_rules.fill_buy(&self.fee_model, *level, buy)
+                {
+                    self.exit.cash -= spent;
+                    self.exit.fees_paid += fee;
+                    self.exit.coins += coins;
+                    self.cash_spent += spent;
+                    self.coins_bought_back += coins;
+                    logs.push((order_price, coins));
+                }
+                *level -= *level * self.step_percentage;
+            }
+        }
+    }
+}
@@ -1,28 +1,31 @@
 use super::super::amm::*;
 use super::super::*;
 use super::*;
-use std::collections::VecDeque;
 
+#[derive(Serialize, Deserialize)]
 pub struct AMMCostAverage {
+    #[serde(default)]
     amm_put_cash: Vec<f64>,
+    #[serde(default)]
     imaginary_total_amm_returned_cash: f64,
+    #[serde(default)]
     finished_amms: usize,
+    #[serde(default)]
     last_amm_uninvested: f64,
 
-    cash_invested: f64,
-    coins_invested: f64,
-
     est_dca_cash_use_ratio: f64,
     amount_round: f64,
+    round_ticks: usize,
     imaginary_amm_ticks_to_expire: usize,
     tick: usize,
 
     rebalance_cash_ratio: f64,
     rebalance_step_percentage: f64,
 
-    amms: VecDeque<AMMBuyBear>,
-
-    buy_logs: BuyLogs,
+    /// Its cash reserve holds the cash of expired AMMs and cash released by decay, put in the
+    /// next AMM.
+    #[serde(flatten)]
+    queue: AMMQueue,
     last_price: f64,
 }
 
@@ -39,9 +42,6 @@ impl AMMCostAverage {
             finished_amms: 0,
             last_amm_uninvested: 0.0,
 
-            cash_invested: 0.0,
-            coins_invested: 0.0,
-
             rebalance_cash_ratio,
             rebalance_step_percentage,
             est_dca_cash_use_ratio,
@@ -49,31 +49,53 @@ impl AMMCostAverage {
             tick: 0,
 
             amount_round: 0.0,
+            round_ticks: DAYS_PER_ROUND,
 
-            amms: Default::default(),
-
-            buy_logs: vec![],
+            queue: AMMQueue::default(),
             last_price: 0.0,
         }
     }
 
+    /// Which bar extreme existing AMMs see first when fed with OHLC bars.
+    pub fn set_intrabar_order(&mut self, order: IntrabarOrder) {
+        self.queue.intrabar_order = order;
+    }
+
+    /// Exchange rules for the orders of the AMMs put from now on.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.queue.exchange_rules = exchange_rules;
+    }
+
+    /// The expiration policy of the AMMs put from now on. With `AtTick` the expiration is
+    /// imaginary and expired AMMs keep buying. With the other policies expired AMMs are dropped,
+    /// their coins stay invested, and their cash and the cash released by decay are put in the
+    /// next AMM. AMMs too spent to buy, see `AMMBuyBear::is_spent`, are dropped the same way, with
+    /// `AtTick` once expired.
+    pub fn set_expiration_policy(&mut self, expiration_policy: ExpirationPolicy) {
+        self.queue.expiration_policy = expiration_policy;
+    }
+
+    /// Let the AMMs buy with `AMMBuyBear::buy_fast`. Their orders are not logged then, so
+    /// `take_orders` returns nothing.
+    pub fn set_fast_path(&mut self, fast_path: bool) {
+        self.queue.fast_path = fast_path;
+    }
+
     fn basic_cash_per_day(&self) -> f64 {
-        self.amount_round / (DAYS_PER_ROUND as f64)
+        self.amount_round / (self.round_ticks as f64)
     }
 
-    fn past_amm_cash_utilization(&self) -> (f64, f64, f64, f64) {
-        if self.tick <= self.imaginary_amm_ticks_to_expire {
-            return (0.0, 0.0, 0.0, std::f64::NAN);
+    /// The cash use of the AMMs past their imaginary expiration, with `ExpirationPolicy::AtTick`.
+    /// Returns: (cash put, cash invested, average cash invested per AMM, over invest factor).
+    pub fn past_amm_cash_utilization(&self) -> (f64, f64, f64, f64) {
+        if self.finished_amms == 0 {
+            return (0.0, 0.0, 0.0, f64::NAN);
         }
 
         let mut cash_put = 0.0;
-        for i in 0..self.finished_amms {
-            cash_put += self.amm_put_cash[i]
+        for cash in &self.amm_put_cash[..self.finished_amms] {
+            cash_put += cash;
         }
-        assert_eq!(
-            self.tick - self.imaginary_amm_ticks_to_expire,
-            self.finished_amms
-        );
 
         let cash_invested = cash_put - self.imaginary_total_amm_returned_cash;
         let average_invested = cash_invested / self.finished_amms as f64;
@@ -95,35 +117,51 @@ impl AMMCostAverage {
         self.amm_put_cash.push(cash_day);
         cash_day
     }
-}
 
-impl CostAverageMethodTrait for AMMCostAverage {
-    fn set_supply(&mut self, amount: f64) {
-        self.amount_round = amount;
-    }
-    fn start_new_round(&mut self, ticks: usize) {
-        assert_eq!(ticks, DAYS_PER_ROUND);
-    }
-    fn feed_price(&mut self, price: f64) {
+    /// Existing AMMs buy at the prices visited within the tick before the close, then a new AMM
+    /// is put at the close price.
+    fn feed_path(&mut self, path: &[f64], price: f64) {
+        self.queue.withdraw(price);
         let basic_cash_per_day = self.basic_cash_per_day();
 
-        for amm in self.amms.iter_mut() {
-            let (expire, cash, coins) = amm.check_expiration(self.tick);
-            if expire {
-                self.last_amm_uninvested = cash - basic_cash_per_day;
-                self.imaginary_total_amm_returned_cash += cash;
-                self.finished_amms += 1;
-
-                // put the money and coins back.
-                amm.cash = cash;
-                amm.coins = coins;
+        // Other than at a tick, AMMs can expire in any order.
+        let mut i = 0;
+        while i < self.queue.amms.len() {
+            let amm = &mut self.queue.amms[i];
+            let (expire, cash, coins) = amm.check_expiration(self.tick, price);
+            let retire = if amm.expiration_policy() == ExpirationPolicy::AtTick {
+                if expire {
+                    if amm.tick_to_expire() == self.tick {
+                        self.last_amm_uninvested = cash - basic_cash_per_day;
+                        self.imaginary_total_amm_returned_cash += cash;
+                        self.finished_amms += 1;
+                    }
+
+                    // put the money and coins back.
+                    amm.cash = cash;
+                    amm.coins = coins;
+                }
+                expire && amm.is_spent()
+            } else {
+                self.queue.cash_reserve += cash;
+                expire || amm.is_spent()
+            };
+
+            if retire {
+                // Its coins stay invested.
+                let amm = self.queue.amms.remove(i).unwrap();
+                self.queue.cash_reserve += amm.cash;
             } else {
-                break;
+                i += 1;
             }
         }
 
-        let amm_cash = self.amm_cash_today();
-        self.amms.push_back(AMMBuyBear::new(
+        for &visited in path {
+            self.queue.buy_down_to(visited);
+        }
+
+        let amm_cash = self.amm_cash_today() + std::mem::take(&mut self.queue.cash_reserve);
+        self.queue.push(AMMBuyBear::new(
             amm_cash,
             price,
             self.tick + self.imaginary_amm_ticks_to_expire,
@@ -131,25 +169,47 @@ impl CostAverageMethodTrait for AMMCostAverage {
             self.rebalance_step_percentage,
         ));
 
-        let mut i = self.amms.len();
-        while i > 0 {
-            i -= 1;
-
-            let amm = &mut self.amms[i];
-            if amm.last_price < price {
-                break;
-            }
-            let (cash, coins) = amm.buy(price, &mut self.buy_logs);
-            self.cash_invested += cash;
-            self.coins_invested += coins;
-        }
+        self.queue.buy_down_to(price);
 
         self.tick += 1;
         self.last_price = price;
     }
+}
+
+impl CostAverageMethodTrait for AMMCostAverage {
+    fn set_supply(&mut self, amount: f64) {
+        self.amount_round = amount.max(0.0);
+        self.queue.withdrawal = (-amount).max(0.0);
+    }
+    fn start_new_round(&mut self, ticks: usize) {
+        self.round_ticks = ticks;
+    }
+    fn feed_price(&mut self, price: f64) {
+        self.feed_path(&[], price);
+    }
+    fn feed_bar(&mut self, bar: &Row) {
+        // The close is visited together with the new AMM.
+        let path = self.queue.path_before_close(bar);
+        self.feed_path(&path, bar.price);
+    }
 
     /// Returns (total invested cash, total invested coins)
     fn get_invest_status(&self) -> (f64, f64) {
-        (self.cash_invested, self.coins_invested)
+        (self.queue.cash_invested, self.queue.coins_invested)
+    }
+    fn get_sell_status(&self) -> (f64, f64) {
+        (self.queue.cash_received, self.queue.coins_sold)
+    }
+    fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.queue.fee_model = fee_model;
+    }
+    fn get_fees_paid(&self) -> f64 {
+        self.queue.fees_paid
+    }
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        self.queue.withdrawal_shortfall
+    }
+    fn take_orders(&mut self) -> BuyLogs {
+        std::mem::take(&mut self.queue.buy_logs)
     }
 }
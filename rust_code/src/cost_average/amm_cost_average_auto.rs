@@ -1,30 +1,29 @@
 use super::super::amm::*;
 use super::super::*;
 use super::*;
-use std::collections::VecDeque;
 
+#[derive(Serialize, Deserialize)]
 pub struct AMMCostAverageAuto {
-    amm_put_cash: Vec<f64>,
     adjusts: Vec<f64>,
+    /// The cash put in the finished AMMs, and the cash they returned including earlier releases.
+    #[serde(default)]
+    finished_amm_put_cash: f64,
     total_amm_returned_cash: f64,
     finished_amms: usize,
     last_amm_uninvested: f64,
 
-    cash_invested: f64,
-    coins_invested: f64,
-
-    cash_reserve: f64,
     est_dca_cash_use_ratio: f64,
     amount_round: f64,
+    round_ticks: usize,
     amm_ticks_to_expire: usize,
     tick: usize,
 
     rebalance_cash_ratio: f64,
     rebalance_step_percentage: f64,
 
-    amms: VecDeque<AMMBuyBear>,
-
-    buy_logs: BuyLogs,
+    /// Its cash reserve is the cash expected to invest, and the cash borrowed when negative.
+    #[serde(flatten)]
+    queue: AMMQueue,
     last_price: f64,
 
     // auto adjust day cash
@@ -41,41 +40,58 @@ impl AMMCostAverageAuto {
         past_uninvested_reinvest_daily_percentage: f64,
     ) -> Self {
         Self {
-            amm_put_cash: vec![],
             adjusts: vec![],
+            finished_amm_put_cash: 0.0,
             total_amm_returned_cash: 0.0,
             finished_amms: 0,
             last_amm_uninvested: 0.0,
 
-            cash_invested: 0.0,
-            coins_invested: 0.0,
-
             rebalance_cash_ratio,
             rebalance_step_percentage,
             est_dca_cash_use_ratio,
             amm_ticks_to_expire,
             tick: 0,
 
-            cash_reserve: 0.0,
             amount_round: 0.0,
+            round_ticks: DAYS_PER_ROUND,
 
-            amms: Default::default(),
-
-            buy_logs: vec![],
+            queue: AMMQueue::default(),
             last_price: 0.0,
             past_uninvested_reinvest_daily_percentage,
         }
     }
 
+    /// See `AMMCostAverage::set_intrabar_order`.
+    pub fn set_intrabar_order(&mut self, order: IntrabarOrder) {
+        self.queue.intrabar_order = order;
+    }
+
+    /// See `AMMCostAverage::set_exchange_rules`.
+    pub fn set_exchange_rules(&mut self, exchange_rules: ExchangeRules) {
+        self.queue.exchange_rules = exchange_rules;
+    }
+
+    /// The expiration policy of the AMMs put from now on. Expired AMMs and AMMs too spent to buy,
+    /// see `AMMBuyBear::is_spent`, are dropped, their coins stay invested and their cash goes to
+    /// the cash reserve.
+    pub fn set_expiration_policy(&mut self, expiration_policy: ExpirationPolicy) {
+        self.queue.expiration_policy = expiration_policy;
+    }
+
+    /// See `AMMCostAverage::set_fast_path`.
+    pub fn set_fast_path(&mut self, fast_path: bool) {
+        self.queue.fast_path = fast_path;
+    }
+
     fn basic_cash_per_day(&self) -> f64 {
-        self.amount_round / (DAYS_PER_ROUND as f64)
+        self.amount_round / (self.round_ticks as f64)
     }
 
     fn cash_unused(&self) -> f64 {
         // Expected to invest - already invested - (borrowed - borrow_repay).
         // self.cash_reserve = expected to invest + borrowed.
-        let mut cash_unused = self.cash_reserve;
-        for amm in &self.amms {
+        let mut cash_unused = self.queue.cash_reserve;
+        for amm in &self.queue.amms {
             cash_unused += amm.cash;
         }
 
@@ -83,19 +99,13 @@ impl AMMCostAverageAuto {
     }
 
     fn past_amm_cash_utilization(&self) -> (f64, f64, f64, f64) {
-        if self.tick <= self.amm_ticks_to_expire {
-            return (0.0, 0.0, 0.0, std::f64::NAN);
+        if self.finished_amms == 0 {
+            return (0.0, 0.0, 0.0, f64::NAN);
         }
+        // One AMM is put every tick.
+        assert_eq!(self.finished_amms + self.queue.amms.len(), self.tick);
 
-        let mut cash_put = 0.0;
-        for i in 0..self.finished_amms {
-            cash_put += self.amm_put_cash[i]
-        }
-        assert!(
-            (self.tick - self.amm_ticks_to_expire) == self.finished_amms
-                || (self.tick - self.amm_ticks_to_expire) == self.finished_amms - 1
-        );
-
+        let cash_put = self.finished_amm_put_cash;
         let cash_invested = cash_put - self.total_amm_returned_cash;
         let average_invested = cash_invested / self.finished_amms as f64;
         // cash_invested = put * (1 - CASH_RATIO) * factor
@@ -112,9 +122,9 @@ impl AMMCostAverageAuto {
     fn log_cash_unused(&self) {
         println!(
             "cash_reserve {} cash_unused {} coin cap {}",
-            self.cash_reserve,
+            self.queue.cash_reserve,
             self.cash_unused(),
-            self.coins_invested * self.last_price,
+            self.queue.coins_invested * self.last_price,
         );
         let (cash_put, finished_amm_cash_invested, average_invested, over_invest_ratio) =
             self.past_amm_cash_utilization();
@@ -132,12 +142,12 @@ impl AMMCostAverageAuto {
         let mut cash_day = self.basic_cash_per_day() / (1.0 - self.rebalance_cash_ratio)
             * self.est_dca_cash_use_ratio;
 
-        if self.tick > self.amm_ticks_to_expire {
+        if self.tick > self.amm_ticks_to_expire && self.finished_amms > 0 {
             // there are two parts: finished amms, unfinished amms. we want to know if
             // we are investing fewer or more than expected and adjust accordingly.
 
             // Check utilization for finished amms.
-            assert_eq!(self.finished_amms, self.tick - self.amm_ticks_to_expire + 1);
+            assert_eq!(self.finished_amms + self.queue.amms.len(), self.tick);
             let (_cash_put, finished_amm_cash_invested, average_invested, over_invest_ratio) =
                 self.past_amm_cash_utilization();
             let expected_spending = self.basic_cash_per_day() * self.finished_amms as f64;
@@ -181,49 +191,48 @@ impl AMMCostAverageAuto {
             // Second part, check the active amms.
         };
 
-        self.cash_reserve += self.basic_cash_per_day() - cash_day;
+        self.queue.cash_reserve += self.basic_cash_per_day() - cash_day;
 
-        self.amm_put_cash.push(cash_day);
         cash_day
     }
-}
 
-impl CostAverageMethodTrait for AMMCostAverageAuto {
-    fn set_supply(&mut self, amount: f64) {
-        self.amount_round = amount;
-        self.cash_reserve = 0.0;
-    }
-    fn start_new_round(&mut self, ticks: usize) {
-        self.log_cash_unused();
-        assert_eq!(ticks, DAYS_PER_ROUND);
-    }
-    fn feed_price(&mut self, price: f64) {
-        loop {
-            if let Some(amm) = self.amms.front_mut() {
-                let (expire, cash, _coins) = amm.check_expiration(self.tick);
-                if expire {
-                    self.last_amm_uninvested = cash - self.basic_cash_per_day();
-                    self.total_amm_returned_cash += cash;
-                    self.finished_amms += 1;
-                    self.cash_reserve += cash;
-                } else {
-                    break;
-                }
+    /// Existing AMMs buy at the prices visited within the tick before the close, then a new AMM
+    /// is put at the close price.
+    fn feed_path(&mut self, path: &[f64], price: f64) {
+        self.queue.withdraw(price);
+        // Other than at a tick, AMMs can expire in any order.
+        let mut i = 0;
+        while i < self.queue.amms.len() {
+            let amm = &mut self.queue.amms[i];
+            let (expire, cash, _coins) = amm.check_expiration(self.tick, price);
+            self.queue.cash_reserve += cash;
+            if expire || amm.is_spent() {
+                // Its coins stay invested, the cash a spent AMM has left goes to the reserve.
+                let amm = self.queue.amms.remove(i).unwrap();
+                self.queue.cash_reserve += amm.cash;
+                let cash = cash + amm.cash;
+                self.finished_amm_put_cash += amm.start_cash();
+                self.total_amm_returned_cash += cash + amm.cash_released();
+                self.last_amm_uninvested = cash - self.basic_cash_per_day();
+                self.finished_amms += 1;
             } else {
-                break;
+                i += 1;
             }
-            self.amms.pop_front();
+        }
+
+        for &visited in path {
+            self.queue.buy_down_to(visited);
         }
 
         let amm_cash = self.amm_cash_today();
-        self.amms.push_back(AMMBuyBear::new(
+        self.queue.push(AMMBuyBear::new(
             amm_cash,
             price,
             self.tick + self.amm_ticks_to_expire,
             self.rebalance_cash_ratio,
             self.rebalance_step_percentage,
         ));
-        if self.amms.len() == 1 {
+        if self.queue.amms.len() == 1 {
             println!(
                 "amm 0 cash put {}, immediate buying {}, price {}, cash_ratio {}",
                 amm_cash,
@@ -233,26 +242,51 @@ impl CostAverageMethodTrait for AMMCostAverageAuto {
             );
         }
 
-        let mut i = self.amms.len();
-        while i > 0 {
-            i -= 1;
-
-            let amm = &mut self.amms[i];
-            if amm.last_price < price {
-                break;
-            }
-            let (cash, coins) = amm.buy(price, &mut self.buy_logs);
-            self.cash_invested += cash;
-            self.coins_invested += coins;
-        }
+        self.queue.buy_down_to(price);
 
         self.tick += 1;
         self.last_price = price;
     }
+}
+
+impl CostAverageMethodTrait for AMMCostAverageAuto {
+    fn set_supply(&mut self, amount: f64) {
+        self.amount_round = amount.max(0.0);
+        self.queue.withdrawal = (-amount).max(0.0);
+    }
+    fn start_new_round(&mut self, ticks: usize) {
+        self.log_cash_unused();
+        self.round_ticks = ticks;
+    }
+    fn feed_price(&mut self, price: f64) {
+        self.feed_path(&[], price);
+    }
+    fn feed_bar(&mut self, bar: &Row) {
+        // The close is visited together with the new AMM.
+        let path = self.queue.path_before_close(bar);
+        self.feed_path(&path, bar.price);
+    }
 
     /// Returns (total invested cash, total invested coins)
     fn get_invest_status(&self) -> (f64, f64) {
+        (self.queue.cash_invested, self.queue.coins_invested)
+    }
+    fn get_sell_status(&self) -> (f64, f64) {
+        (self.queue.cash_received, self.queue.coins_sold)
+    }
+    fn log_status(&self) {
         self.log_cash_unused();
-        (self.cash_invested, self.coins_invested)
+    }
+    fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.queue.fee_model = fee_model;
+    }
+    fn get_fees_paid(&self) -> f64 {
+        self.queue.fees_paid
+    }
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        self.queue.withdrawal_shortfall
+    }
+    fn take_orders(&mut self) -> BuyLogs {
+        std::mem::take(&mut self.queue.buy_logs)
     }
 }
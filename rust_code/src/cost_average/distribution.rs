@@ -0,0 +1,80 @@
+use super::*;
+
+/// Summary of a sample distribution.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub p5: f64,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub mean: f64,
+}
+
+impl Percentiles {
+    /// Samples that are not finite, e.g. the NaN average price of a path without purchases, are
+    /// left out.
+    pub fn new(samples: &[f64]) -> Self {
+        let mut sorted: Vec<f64> = samples.iter().cloned().filter(|x| x.is_finite()).collect();
+        sorted.sort_by(f64::total_cmp);
+        Self {
+            p5: percentile(&sorted, 0.05),
+            p25: percentile(&sorted, 0.25),
+            p50: percentile(&sorted, 0.5),
+            p75: percentile(&sorted, 0.75),
+            p95: percentile(&sorted, 0.95),
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        }
+    }
+}
+
+/// Linear interpolation between the closest ranks of the sorted samples.
+pub fn percentile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = q * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CostAverageDistribution {
+    pub invested_cash: Percentiles,
+    pub final_coins: Percentiles,
+    pub average_price: Percentiles,
+}
+
+/// Runs `invest_rounds` over each path with a fresh method from `new_method`, investing from the
+/// first to the last row, and returns the distribution of the results. Empty paths are skipped.
+pub fn cost_average_distribution<M: CostAverageMethodTrait>(
+    paths: &[Vec<Row>],
+    new_method: impl Fn() -> M,
+    round_schedule: &RoundSchedule,
+    supply_schedule: &SupplySchedule,
+) -> CostAverageDistribution {
+    let mut invested_cash = vec![];
+    let mut final_coins = vec![];
+    let mut average_price = vec![];
+    for rows in paths.iter().filter(|rows| !rows.is_empty()) {
+        let mut invest_method = new_method();
+        invest_rounds(
+            &mut invest_method,
+            rows,
+            round_schedule,
+            supply_schedule,
+            |_, _| {},
+        );
+        let (cash, coins) = invest_method.get_invest_status();
+        invested_cash.push(cash);
+        final_coins.push(coins);
+        average_price.push(average_purchase_price(&invest_method));
+    }
+
+    CostAverageDistribution {
+        invested_cash: Percentiles::new(&invested_cash),
+        final_coins: Percentiles::new(&final_coins),
+        average_price: Percentiles::new(&average_price),
+    }
+}
@@ -9,7 +9,7 @@ pub struct Fluctuation {
 
 impl Fluctuation {
     pub fn new(price_rows: &[Row], amm_last_days_analysis: &[usize]) -> Self {
-        if price_rows.len() == 0 {
+        if price_rows.is_empty() {
             return Self {
                 price_rows: vec![],
                 amm_last_days_analysis: amm_last_days_analysis.to_vec(),
@@ -42,7 +42,7 @@ impl Fluctuation {
             later_lowest_price[index].push(lowest_price);
             max_later_drop_ratio[index].push(1.0 - lowest_price / today_close);
 
-            while let Some((price, row_index)) = lowest_price_stack.last().cloned() {
+            while let Some((price, _row_index)) = lowest_price_stack.last().cloned() {
                 if today_close < price {
                     lowest_price_stack.pop();
                 } else {
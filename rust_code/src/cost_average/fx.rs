@@ -0,0 +1,117 @@
+use super::*;
+use std::error::Error;
+use std::fmt;
+
+/// The fx series has no rate for the day of the row at `index`, or fewer rows.
+#[derive(Debug, PartialEq)]
+pub struct FxAlignmentError {
+    pub index: usize,
+}
+
+impl fmt::Display for FxAlignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fx series not aligned at row {}", self.index)
+    }
+}
+
+impl Error for FxAlignmentError {}
+
+/// Converts USD `rows` into the home currency, `fx` gives home currency units per USD (e.g. EUR
+/// per USD) for the same days. Series with gaps can be aligned first with
+/// `price_data::PricePanel::align` and a `MissingPolicy`.
+pub fn to_home_currency(rows: &[Row], fx: &[Row]) -> Result<Vec<Row>, FxAlignmentError> {
+    if rows.len() != fx.len() {
+        return Err(FxAlignmentError {
+            index: rows.len().min(fx.len()),
+        });
+    }
+    rows.iter()
+        .zip(fx)
+        .enumerate()
+        .map(|(index, (row, rate))| {
+            if row.day() != rate.day() {
+                return Err(FxAlignmentError { index });
+            }
+            let rate = rate.price;
+            Ok(Row {
+                price: row.price * rate,
+                open: row.open.map(|price| price * rate),
+                high: row.high.map(|price| price * rate),
+                low: row.low.map(|price| price * rate),
+                ..row.clone()
+            })
+        })
+        .collect()
+}
+
+/// Results of a backtest funded in a home currency.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FxReport {
+    pub invested_home: f64,
+    /// The invested cash converted to USD at the rate of each tick.
+    pub invested_usd: f64,
+    /// The cash received by sales, withdrawals included.
+    pub received_home: f64,
+    /// The cash received converted to USD at the rate of each tick.
+    pub received_usd: f64,
+    pub coins: f64,
+    /// The average purchase prices, NaN without purchases.
+    pub average_price_home: f64,
+    pub average_price_usd: f64,
+    pub value_home: f64,
+    pub value_usd: f64,
+    pub profit_home: f64,
+    pub profit_usd: f64,
+}
+
+/// Invests the home currency supply of `supply_schedule` in the rounds of `round_schedule`.
+/// The method sees prices in the home currency; the cash it spends is converted back to USD at
+/// the rate of the tick it is spent, so the USD cost basis follows the exchange rate. Fails
+/// before investing if `fx` is not aligned with `rows`, see `to_home_currency`.
+pub fn fx_cost_average(
+    invest_method: &mut impl CostAverageMethodTrait,
+    rows: &[Row],
+    fx: &[Row],
+    round_schedule: &RoundSchedule,
+    supply_schedule: &SupplySchedule,
+) -> Result<FxReport, FxAlignmentError> {
+    let home_rows = to_home_currency(rows, fx)?;
+
+    let mut invested_usd = 0.0;
+    let mut received_usd = 0.0;
+    let mut last_cash = 0.0;
+    let mut last_received = 0.0;
+    invest_rounds(
+        invest_method,
+        &home_rows,
+        round_schedule,
+        supply_schedule,
+        |index, invest_method| {
+            let (cash, _coins) = invest_method.get_invest_status();
+            let (received, _coins_sold) = invest_method.get_sell_status();
+            invested_usd += (cash - last_cash) / fx[index].price;
+            received_usd += (received - last_received) / fx[index].price;
+            last_cash = cash;
+            last_received = received;
+        },
+    );
+
+    let (invested_home, coins) = invest_method.get_invest_status();
+    let (received_home, _coins_sold) = invest_method.get_sell_status();
+    let average_price_home = average_purchase_price(invest_method);
+    let value_home = coins * home_rows.last().map_or(0.0, |row| row.price);
+    let value_usd = coins * rows.last().map_or(0.0, |row| row.price);
+    Ok(FxReport {
+        invested_home,
+        invested_usd,
+        received_home,
+        received_usd,
+        coins,
+        average_price_home,
+        average_price_usd: average_price_home * invested_usd / invested_home,
+        value_home,
+        value_usd,
+        profit_home: value_home + received_home - invested_home,
+        profit_usd: value_usd + received_usd - invested_usd,
+    })
+}
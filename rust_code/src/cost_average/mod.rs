@@ -1,58 +1,107 @@
+use super::amm::{AMMBuyBear, ExchangeRules, ExpirationPolicy, FeeModel};
 use super::*;
+use std::collections::VecDeque;
 
 pub mod amm_cost_average;
 pub mod amm_cost_average_auto;
+pub mod distribution;
 pub mod fluctuation;
+pub mod fx;
+pub mod rounds;
+pub mod supply;
+pub mod value_average;
 
 pub use self::amm_cost_average::AMMCostAverage;
 pub use self::amm_cost_average_auto::AMMCostAverageAuto;
+pub use self::distribution::{cost_average_distribution, CostAverageDistribution, Percentiles};
+pub use self::fx::{fx_cost_average, FxAlignmentError, FxReport};
+pub use self::rounds::RoundSchedule;
+pub use self::supply::SupplySchedule;
+pub use self::value_average::ValueAverage;
 
 pub const DAYS_PER_ROUND: usize = 30;
 pub const DOLLAR_COST_AVERAGE_SUPPLY: (f64, usize) = (2000.0, DAYS_PER_ROUND);
 
 pub trait CostAverageMethodTrait {
+    /// The cash of the rounds from now on. A negative amount is a withdrawal: nothing is
+    /// invested and the amount is taken out in the next tick.
     fn set_supply(&mut self, amount: f64);
     fn start_new_round(&mut self, ticks: usize);
     fn feed_price(&mut self, price: f64);
+    /// Feed a whole bar, methods that can fill within the bar override it.
+    fn feed_bar(&mut self, bar: &Row) {
+        self.feed_price(bar.price);
+    }
     /// Returns (total invested cash, total invested coins)
     fn get_invest_status(&self) -> (f64, f64);
+    /// Returns (total cash received, total coins sold) by the sales, withdrawals included. The
+    /// invested cash counts purchases only, so the coins bought are the invested coins plus the
+    /// coins sold.
+    fn get_sell_status(&self) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+    /// Prints the state of the method, `dollar_cost_average` calls it with each status it reads.
+    fn log_status(&self) {}
+    /// Methods without a fee model trade for free.
+    fn set_fee_model(&mut self, _fee_model: FeeModel) {}
+    /// Returns the fees paid, including the spread paid by market orders.
+    fn get_fees_paid(&self) -> f64 {
+        0.0
+    }
+    /// Returns the part of the withdrawals left unmet when the cash and coins ran out.
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        0.0
+    }
+    /// Returns the orders filled since the last call.
+    fn take_orders(&mut self) -> BuyLogs {
+        vec![]
+    }
 }
 
 // Returns the (additionally invested cash, final amount of coins, average coin purchase price)
-pub fn dollar_cost_average(
+#[allow(clippy::too_many_arguments)]
+pub fn dollar_cost_average<M: CostAverageMethodTrait>(
     bull_start_index: usize,
     bear_start_index: usize,
     bear_start_coins: f64,
     lowest_date_index: usize,
     cash_invested: f64,
     begin_total_asset: f64,
-    invest_method: &mut impl CostAverageMethodTrait,
+    invest_method: &mut M,
     rows: &[Row],
+    round_schedule: &RoundSchedule,
+    supply_schedule: &SupplySchedule,
 ) -> (f64, f64, f64) {
-    invest_method.set_supply(DOLLAR_COST_AVERAGE_SUPPLY.0);
     let mut worst_coins_invested = bear_start_coins;
     let mut worst_cash_invested = cash_invested;
+    // The status before the lowest row is fed.
+    let mut worst_status = |invest_method: &M| {
+        invest_method.log_status();
+        let (cash_invested, coins_invested) = invest_method.get_invest_status();
+        worst_coins_invested += coins_invested;
+        worst_cash_invested += cash_invested;
+    };
 
-    let mut round_ticks = 0;
     let lowest_price = rows[lowest_date_index].price;
-    for index in bear_start_index..bull_start_index {
-        if index == lowest_date_index {
-            let (cash_invested, coins_invested) = invest_method.get_invest_status();
-            worst_coins_invested += coins_invested;
-            worst_cash_invested += cash_invested;
-        }
-        if round_ticks == 0 {
-            invest_method.start_new_round(DOLLAR_COST_AVERAGE_SUPPLY.1);
-        }
-        round_ticks += 1;
-        if round_ticks == DOLLAR_COST_AVERAGE_SUPPLY.1 {
-            round_ticks = 0;
-        }
-
-        invest_method.feed_price(rows[index].price);
+    if lowest_date_index == bear_start_index {
+        worst_status(invest_method);
     }
+    invest_rounds(
+        invest_method,
+        &rows[bear_start_index..bull_start_index],
+        round_schedule,
+        supply_schedule,
+        |index, invest_method| {
+            if bear_start_index + index + 1 == lowest_date_index
+                && lowest_date_index < bull_start_index
+            {
+                worst_status(invest_method);
+            }
+        },
+    );
+    invest_method.log_status();
     let (bear_invest_amount, bear_invest_coins) = invest_method.get_invest_status();
-    let bear_invest_average_price = bear_invest_amount / bear_invest_coins;
+    let bear_invest_average_price = average_purchase_price(invest_method);
     println!(
         "At btc lowest price {}, total invested cash {} of {}, BTC {}. The maximum potential loss \
         if btc goes to 300: {} of {}.",
@@ -75,57 +124,343 @@ pub fn dollar_cost_average(
     )
 }
 
-#[derive(Default)]
-pub struct DollarCostAverage {
-    amount_round: f64,
+/// Feeds every row to `invest_method`, starting the rounds of `round_schedule` with the supply
+/// of `supply_schedule`, and calls `on_tick` with the index of the row after it is fed.
+pub fn invest_rounds<M: CostAverageMethodTrait + ?Sized>(
+    invest_method: &mut M,
+    rows: &[Row],
+    round_schedule: &RoundSchedule,
+    supply_schedule: &SupplySchedule,
+    mut on_tick: impl FnMut(usize, &M),
+) {
+    let mut rounds = round_schedule
+        .rounds(rows)
+        .into_iter()
+        .enumerate()
+        .peekable();
+    for (index, row) in rows.iter().enumerate() {
+        if let Some(&(round, (begin, ticks))) = rounds.peek() {
+            if begin == index {
+                invest_method.set_supply(supply_schedule.supply(round));
+                invest_method.start_new_round(ticks);
+                rounds.next();
+            }
+        }
+
+        invest_method.feed_bar(row);
+        on_tick(index, invest_method);
+    }
+}
+
+/// The cash invested per coin bought, NaN without purchases.
+pub fn average_purchase_price(invest_method: &impl CostAverageMethodTrait) -> f64 {
+    let (cash_invested, coins_invested) = invest_method.get_invest_status();
+    let coins_bought = coins_invested + invest_method.get_sell_status().1;
+    if coins_bought > 0.0 {
+        cash_invested / coins_bought
+    } else {
+        f64::NAN
+    }
+}
+
+/// Spends `cash` on a market order at mid `price`.
+/// Returns: (coins bought, fees and spread paid).
+fn market_buy(fee_model: &FeeModel, cash: f64, price: f64) -> (f64, f64) {
+    let fee = fee_model.taker(cash).min(cash);
+    let buy_price = fee_model.market_buy_price(price);
+    let coins = (cash - fee) / buy_price;
+
+    (coins, fee + (cash - fee) * (1.0 - price / buy_price))
+}
+
+/// Sells at most `coins` for about `cash` with a market order at mid `price`.
+/// Returns: (cash received after fees, coins sold, fees and spread paid).
+fn market_sell(fee_model: &FeeModel, cash: f64, coins: f64, price: f64) -> (f64, f64, f64) {
+    let sell_price = fee_model.market_sell_price(price);
+    let coins_sold = (cash / sell_price).min(coins).max(0.0);
+    let notional = coins_sold * sell_price;
+    let fee = fee_model.taker(notional).min(notional);
+
+    (
+        notional - fee,
+        coins_sold,
+        fee + coins_sold * (price - sell_price),
+    )
+}
+
+/// Sells at most `coins` with a market order at mid `price`, enough to receive `withdrawal`
+/// after fees.
+/// Returns: (cash received after fees, coins sold, fees and spread paid, withdrawal left unmet).
+fn sell_for_withdrawal(
+    fee_model: &FeeModel,
+    withdrawal: f64,
+    coins: f64,
+    price: f64,
+) -> (f64, f64, f64, f64) {
+    let notional = (withdrawal + fee_model.fixed_fee) / (1.0 - fee_model.taker_fee);
+    let (received, coins_sold, fees) = market_sell(fee_model, notional, coins, price);
+    let shortfall = if notional / fee_model.market_sell_price(price) > coins {
+        (withdrawal - received).max(0.0)
+    } else {
+        0.0
+    };
+
+    (received, coins_sold, fees, shortfall)
+}
+
+/// The AMMs of the AMM methods, the newest last, and the cash and coins they trade.
+#[derive(Default, Serialize, Deserialize)]
+struct AMMQueue {
+    cash_invested: f64,
+    coins_invested: f64,
+    #[serde(default)]
+    cash_received: f64,
+    #[serde(default)]
+    coins_sold: f64,
+    /// Cash to take out in the next tick.
+    #[serde(default)]
+    withdrawal: f64,
+    #[serde(default)]
+    withdrawal_shortfall: f64,
+
+    amms: VecDeque<AMMBuyBear>,
+    /// Cash of the method outside the AMMs, taken first by a withdrawal.
+    #[serde(default)]
+    cash_reserve: f64,
+
+    buy_logs: BuyLogs,
+    intrabar_order: IntrabarOrder,
+    fee_model: FeeModel,
+    exchange_rules: ExchangeRules,
+    expiration_policy: ExpirationPolicy,
+    #[serde(default)]
+    fast_path: bool,
+    fees_paid: f64,
+}
+
+impl AMMQueue {
+    /// The prices visited within `bar` before the close, in the intrabar order.
+    fn path_before_close(&self, bar: &Row) -> Vec<f64> {
+        let mut path = bar.intrabar_path(self.intrabar_order);
+        path.pop();
+        path
+    }
+
+    /// Puts `amm` after the others, with the fee model, exchange rules and expiration policy of
+    /// the queue.
+    fn push(&mut self, mut amm: AMMBuyBear) {
+        amm.set_fee_model(self.fee_model);
+        amm.set_exchange_rules(self.exchange_rules);
+        amm.set_expiration_policy(self.expiration_policy);
+        self.amms.push_back(amm);
+    }
+
+    /// Takes a pending withdrawal out of `cash_reserve` first, then out of the uninvested cash of
+    /// the newest AMMs, then sells coins with one market order at mid `price` for the rest. Of
+    /// the coins invested, those of the AMMs are sold first.
+    fn withdraw(&mut self, price: f64) {
+        if self.withdrawal <= 0.0 {
+            return;
+        }
+        let withdrawal = std::mem::take(&mut self.withdrawal);
+        let take = self.cash_reserve.min(withdrawal).max(0.0);
+        self.cash_reserve -= take;
+        let mut rest = withdrawal - take;
+        for amm in self.amms.iter_mut().rev() {
+            rest -= amm.release_cash(rest);
+        }
+        if rest <= 0.0 {
+            return;
+        }
+
+        let amm_coins: f64 = self.amms.iter().map(|amm| amm.coins).sum();
+        let (received, coins_sold, fees, shortfall) = sell_for_withdrawal(
+            &self.fee_model,
+            rest,
+            self.coins_invested.max(amm_coins),
+            price,
+        );
+        let mut unsold = coins_sold;
+        for amm in self.amms.iter_mut().rev() {
+            let coins = amm.coins.min(unsold);
+            amm.coins -= coins;
+            unsold -= coins;
+        }
+        if coins_sold > 0.0 {
+            self.buy_logs.push((price, -coins_sold));
+        }
+        self.cash_received += received;
+        self.coins_sold += coins_sold;
+        self.coins_invested -= coins_sold;
+        self.fees_paid += fees;
+        self.withdrawal_shortfall += shortfall;
+    }
+
+    /// The newest AMMs buy down to `price`, until one whose last price is below it.
+    fn buy_down_to(&mut self, price: f64) {
+        for amm in self.amms.iter_mut().rev() {
+            if amm.last_price < price {
+                break;
+            }
+            let fees_paid = amm.fees_paid;
+            let (cash, coins) = if self.fast_path {
+                amm.buy_fast(price)
+            } else {
+                amm.buy(price, &mut self.buy_logs)
+            };
+            self.cash_invested += cash;
+            self.coins_invested += coins;
+            self.fees_paid += amm.fees_paid - fees_paid;
+        }
+    }
+}
+
+/// The cash and coins of a method trading with market orders.
+#[derive(Default, Serialize, Deserialize)]
+struct MarketAccount {
+    /// Cash to take out in the next tick.
+    #[serde(default)]
+    withdrawal: f64,
+    #[serde(default)]
+    withdrawal_shortfall: f64,
+    /// The cash of the purchases and the coins held.
     total_cash: f64,
     total_coins: f64,
+    /// The cash received by the sales and the coins sold.
+    #[serde(default)]
+    cash_received: f64,
+    #[serde(default)]
+    coins_sold: f64,
+    orders: BuyLogs,
+    fee_model: FeeModel,
+    fees_paid: f64,
+}
+
+impl MarketAccount {
+    fn buy(&mut self, cash: f64, price: f64) {
+        let (coins, fees) = market_buy(&self.fee_model, cash, price);
+        self.total_cash += cash;
+        self.total_coins += coins;
+        self.fees_paid += fees;
+        self.orders.push((price, coins));
+    }
+
+    /// Sells coins for about `cash`.
+    /// Returns: the cash received after fees.
+    fn sell(&mut self, cash: f64, price: f64) -> f64 {
+        let (received, coins, fees) = market_sell(&self.fee_model, cash, self.total_coins, price);
+        self.sold(received, coins, fees, price);
+        received
+    }
+
+    fn sold(&mut self, received: f64, coins: f64, fees: f64, price: f64) {
+        if coins > 0.0 {
+            self.cash_received += received;
+            self.coins_sold += coins;
+            self.total_coins -= coins;
+            self.fees_paid += fees;
+            self.orders.push((price, -coins));
+        }
+    }
+
+    /// Sells coins for a pending withdrawal.
+    fn withdraw(&mut self, price: f64) {
+        if self.withdrawal > 0.0 {
+            let (received, coins, fees, shortfall) =
+                sell_for_withdrawal(&self.fee_model, self.withdrawal, self.total_coins, price);
+            self.sold(received, coins, fees, price);
+            self.withdrawal_shortfall += shortfall;
+            self.withdrawal = 0.0;
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct DollarCostAverage {
+    amount_round: f64,
     tick: i32,
+    #[serde(flatten)]
+    account: MarketAccount,
 }
 
 impl CostAverageMethodTrait for DollarCostAverage {
     fn set_supply(&mut self, amount: f64) {
-        self.amount_round = amount;
+        self.amount_round = amount.max(0.0);
+        self.account.withdrawal = (-amount).max(0.0);
     }
     fn start_new_round(&mut self, _ticks: usize) {
         self.tick = 0;
     }
     fn feed_price(&mut self, price: f64) {
-        if self.tick == 0 {
-            self.total_cash += self.amount_round;
-            self.total_coins += self.amount_round / price;
+        self.account.withdraw(price);
+        if self.tick == 0 && self.amount_round > 0.0 {
+            self.account.buy(self.amount_round, price);
             self.tick = -1;
         }
     }
 
     /// Returns (total invested cash, total invested coins)
     fn get_invest_status(&self) -> (f64, f64) {
-        (self.total_cash, self.total_coins)
+        (self.account.total_cash, self.account.total_coins)
+    }
+    fn get_sell_status(&self) -> (f64, f64) {
+        (self.account.cash_received, self.account.coins_sold)
+    }
+    fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.account.fee_model = fee_model;
+    }
+    fn get_fees_paid(&self) -> f64 {
+        self.account.fees_paid
+    }
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        self.account.withdrawal_shortfall
+    }
+    fn take_orders(&mut self) -> BuyLogs {
+        std::mem::take(&mut self.account.orders)
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct DailyDollarCostAverage {
     amount_round: f64,
-    total_cash: f64,
-    total_coins: f64,
     ticks: f64,
+    #[serde(flatten)]
+    account: MarketAccount,
 }
 
 impl CostAverageMethodTrait for DailyDollarCostAverage {
     fn set_supply(&mut self, amount: f64) {
-        self.amount_round = amount;
+        self.amount_round = amount.max(0.0);
+        self.account.withdrawal = (-amount).max(0.0);
     }
     fn start_new_round(&mut self, ticks: usize) {
         self.ticks = ticks as f64;
     }
     fn feed_price(&mut self, price: f64) {
-        self.total_cash += self.amount_round / self.ticks;
-        self.total_coins += self.amount_round / self.ticks / price;
+        self.account.withdraw(price);
+        if self.amount_round <= 0.0 {
+            return;
+        }
+        self.account.buy(self.amount_round / self.ticks, price);
     }
 
     /// Returns (total invested cash, total invested coins)
     fn get_invest_status(&self) -> (f64, f64) {
-        (self.total_cash, self.total_coins)
+        (self.account.total_cash, self.account.total_coins)
+    }
+    fn get_sell_status(&self) -> (f64, f64) {
+        (self.account.cash_received, self.account.coins_sold)
+    }
+    fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.account.fee_model = fee_model;
+    }
+    fn get_fees_paid(&self) -> f64 {
+        self.account.fees_paid
+    }
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        self.account.withdrawal_shortfall
+    }
+    fn take_orders(&mut self) -> BuyLogs {
+        std::mem::take(&mut self.account.orders)
     }
 }
@@ -0,0 +1,126 @@
+use super::*;
+use chrono::{Datelike, Duration, Weekday};
+use price_data::Interval;
+
+/// When a new round of the cost average methods starts, e.g. on paydays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundSchedule {
+    /// A round every `n` ticks from the first row, `FixedTicks(DAYS_PER_ROUND)` is the original
+    /// schedule. `n` must be at least 1.
+    FixedTicks(usize),
+    /// Every Monday.
+    Weekly,
+    /// Every other Monday, in the even weeks counted from the first week of the common era.
+    Biweekly,
+    /// On this day of every month, or on the last day of shorter months.
+    MonthlyOnDay(u32),
+    /// On the last weekday of every month.
+    LastBusinessDay,
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .unwrap()
+        .pred_opt()
+        .unwrap()
+        .day()
+}
+
+fn previous_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
+
+impl RoundSchedule {
+    /// The payday of a month, `None` for the weekly schedules.
+    fn payday_in_month(&self, year: i32, month: u32) -> Option<NaiveDate> {
+        match *self {
+            RoundSchedule::MonthlyOnDay(day) => {
+                let day = day.max(1).min(days_in_month(year, month));
+                NaiveDate::from_ymd_opt(year, month, day)
+            }
+            RoundSchedule::LastBusinessDay => {
+                let mut date =
+                    NaiveDate::from_ymd_opt(year, month, days_in_month(year, month)).unwrap();
+                while date.weekday() == Weekday::Sat || date.weekday() == Weekday::Sun {
+                    date = date.pred_opt().unwrap();
+                }
+                Some(date)
+            }
+            _ => None,
+        }
+    }
+
+    /// The last payday on or before `date`, `None` for `FixedTicks`.
+    pub fn payday_on_or_before(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        match *self {
+            RoundSchedule::FixedTicks(_) => None,
+            RoundSchedule::Weekly => Some(monday),
+            RoundSchedule::Biweekly => {
+                let week = Interval::Weekly.bucket(monday.and_hms_opt(0, 0, 0).unwrap());
+                Some(monday - Duration::weeks(week.rem_euclid(2)))
+            }
+            RoundSchedule::MonthlyOnDay(_) | RoundSchedule::LastBusinessDay => {
+                let payday = self.payday_in_month(date.year(), date.month())?;
+                if date >= payday {
+                    Some(payday)
+                } else {
+                    let (year, month) = previous_month(date.year(), date.month());
+                    self.payday_in_month(year, month)
+                }
+            }
+        }
+    }
+
+    /// The first payday after `date`, `None` for `FixedTicks`.
+    pub fn next_payday(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let payday = self.payday_on_or_before(date)?;
+        match *self {
+            RoundSchedule::Weekly => Some(payday + Duration::weeks(1)),
+            RoundSchedule::Biweekly => Some(payday + Duration::weeks(2)),
+            _ => {
+                let (year, month) = if payday.month() == 12 {
+                    (payday.year() + 1, 1)
+                } else {
+                    (payday.year(), payday.month() + 1)
+                };
+                self.payday_in_month(year, month)
+            }
+        }
+    }
+
+    /// Splits `rows` into rounds.
+    /// Returns: (index of the first row, ticks) of every round. The ticks of a calendar round
+    /// are its rows; the last round of `FixedTicks` can have fewer rows than ticks.
+    pub fn rounds(&self, rows: &[Row]) -> Vec<(usize, usize)> {
+        if let RoundSchedule::FixedTicks(ticks) = *self {
+            assert!(ticks > 0, "FixedTicks needs at least one tick per round");
+            return (0..rows.len())
+                .step_by(ticks)
+                .map(|begin| (begin, ticks))
+                .collect();
+        }
+
+        let mut rounds: Vec<(usize, usize)> = vec![];
+        let mut last_payday = None;
+        for (index, row) in rows.iter().enumerate() {
+            let payday = self.payday_on_or_before(row.day());
+            if rounds.is_empty() || payday != last_payday {
+                rounds.push((index, 0));
+                last_payday = payday;
+            }
+            rounds.last_mut().unwrap().1 += 1;
+        }
+
+        rounds
+    }
+}
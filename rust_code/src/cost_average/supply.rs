@@ -0,0 +1,77 @@
+/// The cash supplied to a cost average method in each round, see `dollar_cost_average`. Rounds
+/// are numbered from 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SupplySchedule {
+    amount: f64,
+    /// (first round, new amount per round).
+    raises: Vec<(usize, f64)>,
+    /// (round, extra amount).
+    bonuses: Vec<(usize, f64)>,
+    /// (first round, rounds) without the regular amount.
+    pauses: Vec<(usize, usize)>,
+    /// (round, amount taken out).
+    withdrawals: Vec<(usize, f64)>,
+}
+
+impl SupplySchedule {
+    /// `amount` every round.
+    pub fn new(amount: f64) -> Self {
+        Self {
+            amount,
+            raises: vec![],
+            bonuses: vec![],
+            pauses: vec![],
+            withdrawals: vec![],
+        }
+    }
+
+    /// The regular amount is `amount` from `round` on, e.g. after a salary raise.
+    pub fn add_raise(&mut self, round: usize, amount: f64) {
+        self.raises.push((round, amount));
+        self.raises.sort_by_key(|raise| raise.0);
+    }
+
+    /// A one-off deposit in `round`, paid during pauses too.
+    pub fn add_bonus(&mut self, round: usize, amount: f64) {
+        self.bonuses.push((round, amount));
+    }
+
+    /// No regular amount for `rounds` rounds from `round` on.
+    pub fn add_pause(&mut self, round: usize, rounds: usize) {
+        self.pauses.push((round, rounds));
+    }
+
+    /// Takes `amount` out in `round`.
+    pub fn add_withdrawal(&mut self, round: usize, amount: f64) {
+        self.withdrawals.push((round, amount));
+    }
+
+    /// The supply of `round`, negative when the withdrawals are larger than the deposits.
+    pub fn supply(&self, round: usize) -> f64 {
+        let paused = self
+            .pauses
+            .iter()
+            .any(|&(first, rounds)| first <= round && round < first + rounds);
+        let mut supply = if paused {
+            0.0
+        } else {
+            self.raises
+                .iter()
+                .rev()
+                .find(|raise| raise.0 <= round)
+                .map_or(self.amount, |raise| raise.1)
+        };
+        for &(bonus_round, amount) in &self.bonuses {
+            if bonus_round == round {
+                supply += amount;
+            }
+        }
+        for &(withdrawal_round, amount) in &self.withdrawals {
+            if withdrawal_round == round {
+                supply -= amount;
+            }
+        }
+
+        supply
+    }
+}
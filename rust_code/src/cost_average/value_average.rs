@@ -0,0 +1,113 @@
+use super::super::amm::FeeModel;
+use super::super::*;
+use super::*;
+
+/// Value averaging: at the start of every round the coins are bought, or sold, so that their
+/// value reaches a target path. The target grows by the round's supply and by `growth_rate`
+/// compounded per round, so a fall in price is bought with more than the supply and a rise
+/// with less. The invested cash counts the purchases only, the sales are reported by
+/// `get_sell_status`.
+#[derive(Serialize, Deserialize)]
+pub struct ValueAverage {
+    growth_rate: f64,
+    allow_sell: bool,
+    max_round_buy: Option<f64>,
+
+    amount_round: f64,
+    target_value: f64,
+    new_round: bool,
+
+    #[serde(flatten)]
+    account: MarketAccount,
+}
+
+impl ValueAverage {
+    /// `growth_rate` is the growth of the target value per round on top of the supply.
+    pub fn new(growth_rate: f64) -> Self {
+        Self {
+            growth_rate,
+            allow_sell: true,
+            max_round_buy: None,
+
+            amount_round: 0.0,
+            target_value: 0.0,
+            new_round: false,
+
+            account: MarketAccount::default(),
+        }
+    }
+
+    /// Whether coins above the target value are sold. Withdrawals are sold either way.
+    pub fn set_allow_sell(&mut self, allow_sell: bool) {
+        self.allow_sell = allow_sell;
+    }
+
+    /// The most cash spent in one round, `None` for no cap. The shortfall stays in the target
+    /// and is bought in later rounds.
+    pub fn set_max_round_buy(&mut self, max_round_buy: Option<f64>) {
+        self.max_round_buy = max_round_buy;
+    }
+
+    /// The value the coins should have in the current round.
+    pub fn target_value(&self) -> f64 {
+        self.target_value
+    }
+
+    /// Sells coins for a pending withdrawal, which lowers the target by the same amount.
+    fn withdraw(&mut self, price: f64) {
+        if self.account.withdrawal > 0.0 {
+            self.target_value = (self.target_value - self.account.withdrawal).max(0.0);
+            self.account.withdraw(price);
+        }
+    }
+
+    fn rebalance(&mut self, price: f64) {
+        self.target_value = self.target_value * (1.0 + self.growth_rate) + self.amount_round;
+        let shortfall = self.target_value - self.account.total_coins * price;
+        if shortfall > 0.0 {
+            let cash = self
+                .max_round_buy
+                .map_or(shortfall, |max| shortfall.min(max));
+            self.account.buy(cash, price);
+        } else if shortfall < 0.0 && self.allow_sell {
+            self.account.sell(-shortfall, price);
+        }
+    }
+}
+
+impl CostAverageMethodTrait for ValueAverage {
+    fn set_supply(&mut self, amount: f64) {
+        self.amount_round = amount.max(0.0);
+        self.account.withdrawal = (-amount).max(0.0);
+    }
+    fn start_new_round(&mut self, _ticks: usize) {
+        self.new_round = true;
+    }
+    fn feed_price(&mut self, price: f64) {
+        self.withdraw(price);
+        if self.new_round {
+            self.rebalance(price);
+            self.new_round = false;
+        }
+    }
+
+    /// Returns (total invested cash, total invested coins)
+    fn get_invest_status(&self) -> (f64, f64) {
+        (self.account.total_cash, self.account.total_coins)
+    }
+    fn get_sell_status(&self) -> (f64, f64) {
+        (self.account.cash_received, self.account.coins_sold)
+    }
+    fn set_fee_model(&mut self, fee_model: FeeModel) {
+        self.account.fee_model = fee_model;
+    }
+    fn get_fees_paid(&self) -> f64 {
+        self.account.fees_paid
+    }
+    fn get_withdrawal_shortfall(&self) -> f64 {
+        self.account.withdrawal_shortfall
+    }
+    fn take_orders(&mut self) -> BuyLogs {
+        std::mem::take(&mut self.account.orders)
+    }
+}
@@ -0,0 +1,204 @@
+use super::*;
+use chrono::Duration;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, Poisson, StandardNormal};
+
+/// Daily steps, drift and volatility are annualized over 365 days since BTC trades every day.
+pub const DAYS_PER_YEAR: f64 = 365.0;
+
+/// Produces synthetic daily price paths.
+pub trait PathGenerator {
+    /// Returns `days` daily closes starting the day after `start_time`.
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        start_price: f64,
+        start_time: NaiveDateTime,
+        days: usize,
+    ) -> Vec<Row>;
+}
+
+/// Generates `count` paths, path i is seeded by `seed + i` so any single path can be reproduced.
+pub fn generate_paths(
+    generator: &impl PathGenerator,
+    count: usize,
+    seed: u64,
+    start_price: f64,
+    start_time: NaiveDateTime,
+    days: usize,
+) -> Vec<Vec<Row>> {
+    (0..count)
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(i as u64));
+            generator.generate(&mut rng, start_price, start_time, days)
+        })
+        .collect()
+}
+
+/// Turns daily log returns into rows.
+fn rows_from_log_returns(
+    start_price: f64,
+    start_time: NaiveDateTime,
+    log_returns: impl Iterator<Item = f64>,
+) -> Vec<Row> {
+    let mut price = start_price;
+    log_returns
+        .enumerate()
+        .map(|(i, log_return)| {
+            price *= log_return.exp();
+            Row::new(start_time + Duration::days(i as i64 + 1), price)
+        })
+        .collect()
+}
+
+/// Geometric Brownian motion, `drift` and `volatility` are annualized.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GbmParams {
+    pub drift: f64,
+    pub volatility: f64,
+}
+
+impl GbmParams {
+    fn log_return(&self, rng: &mut StdRng) -> f64 {
+        let dt = 1.0 / DAYS_PER_YEAR;
+        let z: f64 = StandardNormal.sample(rng);
+        (self.drift - self.volatility * self.volatility / 2.0) * dt
+            + self.volatility * dt.sqrt() * z
+    }
+}
+
+impl PathGenerator for GbmParams {
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        start_price: f64,
+        start_time: NaiveDateTime,
+        days: usize,
+    ) -> Vec<Row> {
+        let log_returns = (0..days).map(|_| self.log_return(rng));
+        rows_from_log_returns(start_price, start_time, log_returns)
+    }
+}
+
+/// Merton jump-diffusion: GBM plus jumps arriving `jump_intensity` times a year on average,
+/// with normally distributed log jump sizes. The diffusion drift is compensated so the expected
+/// return stays `gbm.drift`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct JumpDiffusionParams {
+    pub gbm: GbmParams,
+    pub jump_intensity: f64,
+    pub jump_mean: f64,
+    pub jump_volatility: f64,
+}
+
+impl PathGenerator for JumpDiffusionParams {
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        start_price: f64,
+        start_time: NaiveDateTime,
+        days: usize,
+    ) -> Vec<Row> {
+        let dt = 1.0 / DAYS_PER_YEAR;
+        let mean_jump =
+            (self.jump_mean + self.jump_volatility * self.jump_volatility / 2.0).exp() - 1.0;
+        let diffusion = GbmParams {
+            drift: self.gbm.drift - self.jump_intensity * mean_jump,
+            volatility: self.gbm.volatility,
+        };
+        let jump_size = Normal::new(self.jump_mean, self.jump_volatility).unwrap();
+        let jumps = if self.jump_intensity > 0.0 {
+            Some(Poisson::new(self.jump_intensity * dt).unwrap())
+        } else {
+            None
+        };
+
+        let log_returns = (0..days).map(|_| {
+            let mut log_return = diffusion.log_return(rng);
+            let count: f64 = jumps.map_or(0.0, |jumps| jumps.sample(rng));
+            for _ in 0..count as usize {
+                log_return += jump_size.sample(rng);
+            }
+            log_return
+        });
+        rows_from_log_returns(start_price, start_time, log_returns)
+    }
+}
+
+/// Markov switching between a bull and a bear GBM regime. Each regime lasts
+/// `*_mean_days` on average, the path starts in the bull regime.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RegimeSwitchingParams {
+    pub bull: GbmParams,
+    pub bear: GbmParams,
+    pub bull_mean_days: f64,
+    pub bear_mean_days: f64,
+}
+
+impl PathGenerator for RegimeSwitchingParams {
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        start_price: f64,
+        start_time: NaiveDateTime,
+        days: usize,
+    ) -> Vec<Row> {
+        let mut bull = true;
+        let log_returns = (0..days).map(|_| {
+            let (regime, mean_days) = if bull {
+                (&self.bull, self.bull_mean_days)
+            } else {
+                (&self.bear, self.bear_mean_days)
+            };
+            let log_return = regime.log_return(rng);
+            if rng.gen::<f64>() < 1.0 / mean_days {
+                bull = !bull;
+            }
+            log_return
+        });
+        rows_from_log_returns(start_price, start_time, log_returns)
+    }
+}
+
+/// Resamples the daily log returns of a history in blocks of `block_len` consecutive days,
+/// which keeps the volatility clustering within a block.
+#[derive(Clone, Debug)]
+pub struct BlockBootstrap {
+    log_returns: Vec<f64>,
+    block_len: usize,
+}
+
+impl BlockBootstrap {
+    pub fn new(rows: &[Row], block_len: usize) -> Self {
+        assert!(block_len > 0);
+        assert!(rows.len() > block_len, "history shorter than a block");
+        let log_returns = rows
+            .windows(2)
+            .map(|pair| (pair[1].price / pair[0].price).ln())
+            .collect();
+
+        Self {
+            log_returns,
+            block_len,
+        }
+    }
+}
+
+impl PathGenerator for BlockBootstrap {
+    fn generate(
+        &self,
+        rng: &mut StdRng,
+        start_price: f64,
+        start_time: NaiveDateTime,
+        days: usize,
+    ) -> Vec<Row> {
+        let mut log_returns = Vec::with_capacity(days);
+        while log_returns.len() < days {
+            let begin = rng.gen_range(0..=self.log_returns.len() - self.block_len);
+            let end = (begin + self.block_len).min(begin + days - log_returns.len());
+            log_returns.extend_from_slice(&self.log_returns[begin..end]);
+        }
+        rows_from_log_returns(start_price, start_time, log_returns.into_iter())
+    }
+}
@@ -5,21 +5,154 @@
 // Modification based on https://github.com/hlb8122/rust-bitcoincash-addr in MIT License.
 // A copy of the original license is included in LICENSE.rust-bitcoincash-addr.
 
+extern crate chrono;
 extern crate csv;
+extern crate rand;
+extern crate rand_distr;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate serde_json;
 
 pub mod amm;
+pub mod checkpoint;
 pub mod cost_average;
+pub mod generator;
+pub mod price_data;
+pub mod stream;
 
-#[derive(Clone, Deserialize, Debug)]
+use chrono::{NaiveDate, NaiveDateTime};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Row {
-    date: String,
-    price: f64,
+    /// The date as written in the source, e.g. "12/1/2017 23:58:00".
+    pub date: String,
+    pub time: NaiveDateTime,
+    /// The close price.
+    pub price: f64,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub volume: Option<f64>,
+}
+
+/// Which extreme of a bar is assumed to be reached first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntrabarOrder {
+    #[default]
+    HighFirst,
+    LowFirst,
+}
+
+/// Accepted date formats, month first for the US style dates in the bundled TSV.
+const DATE_TIME_FORMATS: [&str; 4] = [
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+];
+const DATE_FORMATS: [&str; 2] = ["%m/%d/%Y", "%Y-%m-%d"];
+
+impl Row {
+    pub fn new(time: NaiveDateTime, price: f64) -> Self {
+        Self {
+            date: time.format("%-m/%-d/%Y %H:%M:%S").to_string(),
+            time,
+            price,
+            open: None,
+            high: None,
+            low: None,
+            volume: None,
+        }
+    }
+
+    pub fn new_bar(
+        time: NaiveDateTime,
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        volume: Option<f64>,
+    ) -> Self {
+        Self {
+            open: Some(open),
+            high: Some(high),
+            low: Some(low),
+            volume,
+            ..Self::new(time, close)
+        }
+    }
+
+    /// Parses `date` with one of the accepted formats, keeping the original string.
+    pub fn parse(date: &str, price: f64) -> Option<Self> {
+        let date = date.trim();
+        let time = parse_date_time(date)?;
+        Some(Self {
+            date: date.to_string(),
+            time,
+            price,
+            open: None,
+            high: None,
+            low: None,
+            volume: None,
+        })
+    }
+
+    pub fn day(&self) -> NaiveDate {
+        self.time.date()
+    }
+
+    /// The bar high, the close price if the row has no OHLC data.
+    pub fn high(&self) -> f64 {
+        self.high.unwrap_or(self.price).max(self.price)
+    }
+
+    /// The bar low, the close price if the row has no OHLC data.
+    pub fn low(&self) -> f64 {
+        self.low.unwrap_or(self.price).min(self.price)
+    }
+
+    /// The prices visited within the bar: open, both extremes in the given order, then close.
+    /// A row without OHLC data only visits its close.
+    pub fn intrabar_path(&self, order: IntrabarOrder) -> Vec<f64> {
+        let mut path = vec![];
+        if let Some(open) = self.open {
+            path.push(open);
+        }
+        match order {
+            IntrabarOrder::HighFirst => {
+                path.push(self.high());
+                path.push(self.low());
+            }
+            IntrabarOrder::LowFirst => {
+                path.push(self.low());
+                path.push(self.high());
+            }
+        }
+        path.push(self.price);
+        path.dedup();
+
+        path
+    }
+}
+
+pub fn parse_date_time(date: &str) -> Option<NaiveDateTime> {
+    for format in DATE_TIME_FORMATS.iter() {
+        if let Ok(time) = NaiveDateTime::parse_from_str(date, format) {
+            return Some(time);
+        }
+    }
+    for format in DATE_FORMATS.iter() {
+        if let Ok(day) = NaiveDate::parse_from_str(date, format) {
+            return day.and_hms_opt(0, 0, 0);
+        }
+    }
+
+    None
 }
 
-type BuyLogs = Vec<(f64, f64)>;
+/// (price, coins) of each filled order, coins are negative for sells.
+pub type BuyLogs = Vec<(f64, f64)>;
 
 #[cfg(test)]
 mod tests;
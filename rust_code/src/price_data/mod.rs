@@ -0,0 +1,141 @@
+use super::*;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub mod panel;
+pub mod resample;
+pub mod source;
+pub mod validate;
+
+pub use self::panel::{MissingPolicy, PricePanel};
+pub use self::resample::{forward_fill, group, resample, Interval};
+pub use self::source::{
+    BinanceKlineSource, CoinGeckoSource, CsvSource, JsonLinesSource, PriceSource, TsvSource,
+};
+pub use self::validate::{validate, PriceIssue};
+
+/// Errors when loading a price history.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// A record which can not be parsed into a `Row`, `line` is 1-based.
+    Parse {
+        line: u64,
+        message: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "io error: {}", e),
+            LoadError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<csv::Error> for LoadError {
+    fn from(e: csv::Error) -> Self {
+        let line = e.position().map(|p| p.line()).unwrap_or(0);
+        if e.is_io_error() {
+            match e.into_kind() {
+                csv::ErrorKind::Io(e) => LoadError::Io(e),
+                _ => unreachable!(),
+            }
+        } else {
+            LoadError::Parse {
+                line,
+                message: e.to_string(),
+            }
+        }
+    }
+}
+
+/// date, close, and the optional open, high, low, volume columns.
+#[derive(Deserialize)]
+struct RawRow {
+    date: String,
+    price: f64,
+    #[serde(default)]
+    open: Option<f64>,
+    #[serde(default)]
+    high: Option<f64>,
+    #[serde(default)]
+    low: Option<f64>,
+    #[serde(default)]
+    volume: Option<f64>,
+}
+
+/// Loads a headerless tab separated price history, one `date\tclose` per line, optionally
+/// followed by `\topen\thigh\tlow\tvolume`. Lines starting with '#' are comments. Dates are
+/// month first, see `parse_date_time`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Row>, LoadError> {
+    load_from_reader(File::open(path)?)
+}
+
+/// Same as `load`, reading from any `Read`.
+pub fn load_from_reader<R: io::Read>(reader: R) -> Result<Vec<Row>, LoadError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(b'\t')
+        .double_quote(false)
+        .escape(Some(b'\\'))
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+    let mut rows = Vec::<Row>::new();
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        let raw: RawRow = record.deserialize(None).map_err(|e| LoadError::Parse {
+            line,
+            message: e.to_string(),
+        })?;
+        let mut row = Row::parse(&raw.date, raw.price).ok_or_else(|| LoadError::Parse {
+            line,
+            message: format!("invalid date {:?}", raw.date),
+        })?;
+        row.open = raw.open;
+        row.high = raw.high;
+        row.low = raw.low;
+        row.volume = raw.volume;
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// Returns the index of the first row on or after `date`. Rows must be sorted by time.
+pub fn index_on_or_after(rows: &[Row], date: NaiveDate) -> Option<usize> {
+    let index = rows.partition_point(|row| row.day() < date);
+    if index < rows.len() {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// Returns the rows in [from, to). Rows must be sorted by time.
+pub fn slice_by_date(rows: &[Row], from: NaiveDate, to: NaiveDate) -> &[Row] {
+    let begin = rows.partition_point(|row| row.day() < from);
+    let end = rows.partition_point(|row| row.day() < to).max(begin);
+    &rows[begin..end]
+}
@@ -0,0 +1,115 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// What to do at a timestamp where a series has no row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissingPolicy {
+    /// Carry the previous close forward. Timestamps before the first row of a series are dropped.
+    ForwardFill,
+    /// Drop the timestamp from every series.
+    Drop,
+}
+
+/// Several price series, e.g. BTC, ETH, USDT and EURUSD, aligned on a common time axis. Rows
+/// are grouped by `interval`, so a daily panel lines up a close stamped 23:58 with one stamped
+/// 00:00 of the same day.
+pub struct PricePanel {
+    interval: Interval,
+    /// Sorted interval numbers, see `Interval::bucket`, present in any series.
+    buckets: Vec<i64>,
+    series: BTreeMap<String, BTreeMap<i64, Row>>,
+}
+
+impl PricePanel {
+    pub fn new(interval: Interval) -> Self {
+        Self {
+            interval,
+            buckets: vec![],
+            series: BTreeMap::new(),
+        }
+    }
+
+    /// Adds or replaces the series of `symbol`. Rows must be sorted by time.
+    pub fn insert(&mut self, symbol: &str, rows: &[Row]) {
+        let mut series = BTreeMap::new();
+        for row in group(rows, self.interval) {
+            series.insert(self.interval.bucket(row.time), row);
+        }
+        self.series.insert(symbol.to_string(), series);
+
+        let mut buckets: Vec<i64> = self
+            .series
+            .values()
+            .flat_map(|series| series.keys().cloned())
+            .collect();
+        buckets.sort_unstable();
+        buckets.dedup();
+        self.buckets = buckets;
+    }
+
+    pub fn symbols(&self) -> Vec<&str> {
+        self.series.keys().map(|symbol| symbol.as_str()).collect()
+    }
+
+    /// The number of timestamps in the union of all series.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// The row of `symbol` at the `index`-th timestamp, if there is one.
+    pub fn get(&self, symbol: &str, index: usize) -> Option<&Row> {
+        let bucket = self.buckets.get(index)?;
+        self.series.get(symbol)?.get(bucket)
+    }
+
+    pub fn price(&self, symbol: &str, index: usize) -> Option<f64> {
+        self.get(symbol, index).map(|row| row.price)
+    }
+
+    /// Returns one series per symbol, all of the same length with rows at the same intervals.
+    /// Returns `None` if a symbol is unknown.
+    pub fn align(&self, symbols: &[&str], missing: MissingPolicy) -> Option<Vec<Vec<Row>>> {
+        let series: Vec<&BTreeMap<i64, Row>> = symbols
+            .iter()
+            .map(|symbol| self.series.get(*symbol))
+            .collect::<Option<_>>()?;
+
+        let mut aligned = vec![vec![]; symbols.len()];
+        let mut last: Vec<Option<(i64, &Row)>> = vec![None; symbols.len()];
+        for &bucket in &self.buckets {
+            let mut rows = vec![];
+            for j in 0..series.len() {
+                match series[j].get(&bucket) {
+                    Some(row) => {
+                        last[j] = Some((bucket, row));
+                        rows.push(Some(row.clone()));
+                    }
+                    None => rows.push(match (missing, last[j]) {
+                        (MissingPolicy::ForwardFill, Some((previous_bucket, previous))) => Some(
+                            forward_fill(previous, self.interval, bucket - previous_bucket),
+                        ),
+                        _ => None,
+                    }),
+                }
+            }
+
+            if rows.iter().all(|row| row.is_some()) {
+                for (j, row) in rows.into_iter().enumerate() {
+                    aligned[j].push(row.unwrap());
+                }
+            }
+        }
+
+        Some(aligned)
+    }
+
+    /// The series of `symbol` alone, forward-filled over the timestamps of the panel.
+    pub fn rows(&self, symbol: &str) -> Option<Vec<Row>> {
+        self.align(&[symbol], MissingPolicy::ForwardFill)
+            .map(|mut aligned| aligned.remove(0))
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+use chrono::{Datelike, Duration, Timelike};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interval {
+    Hourly,
+    Daily,
+    /// Weeks starting on Monday.
+    Weekly,
+}
+
+impl Interval {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Interval::Hourly => Duration::hours(1),
+            Interval::Daily => Duration::days(1),
+            Interval::Weekly => Duration::weeks(1),
+        }
+    }
+
+    /// The number of the interval containing `time`, consecutive intervals have consecutive
+    /// numbers.
+    pub fn bucket(&self, time: NaiveDateTime) -> i64 {
+        let days = time.date().num_days_from_ce() as i64;
+        match self {
+            Interval::Hourly => days * 24 + time.hour() as i64,
+            Interval::Daily => days,
+            // Day 1 of the common era is a Monday.
+            Interval::Weekly => (days - 1).div_euclid(7),
+        }
+    }
+}
+
+/// Aggregates sorted `rows` into one row per `interval` with data. A bucket with several rows
+/// becomes an OHLC bar stamped with the time of its last row; a bucket with a single row is kept
+/// as it is.
+pub fn group(rows: &[Row], interval: Interval) -> Vec<Row> {
+    let mut grouped = vec![];
+    let mut begin = 0;
+    while begin < rows.len() {
+        let bucket = interval.bucket(rows[begin].time);
+        let mut end = begin + 1;
+        while end < rows.len() && interval.bucket(rows[end].time) == bucket {
+            end += 1;
+        }
+
+        grouped.push(aggregate(&rows[begin..end]));
+        begin = end;
+    }
+
+    grouped
+}
+
+/// Like `group`, and missing intervals are forward-filled with the previous close.
+pub fn resample(rows: &[Row], interval: Interval) -> Vec<Row> {
+    let mut resampled: Vec<Row> = vec![];
+    for row in group(rows, interval) {
+        if let Some(previous) = resampled.last().cloned() {
+            let gap = interval.bucket(row.time) - interval.bucket(previous.time);
+            for k in 1..gap {
+                resampled.push(forward_fill(&previous, interval, k));
+            }
+        }
+        resampled.push(row);
+    }
+
+    resampled
+}
+
+/// The close of `previous` carried `k` intervals later.
+pub fn forward_fill(previous: &Row, interval: Interval, k: i64) -> Row {
+    Row::new(
+        previous.time + interval.duration() * k as i32,
+        previous.price,
+    )
+}
+
+fn aggregate(rows: &[Row]) -> Row {
+    if rows.len() == 1 {
+        return rows[0].clone();
+    }
+
+    let first = &rows[0];
+    let last = &rows[rows.len() - 1];
+    let mut high = first.high();
+    let mut low = first.low();
+    let mut volume = None;
+    for row in rows {
+        high = high.max(row.high());
+        low = low.min(row.low());
+        if let Some(v) = row.volume {
+            volume = Some(volume.unwrap_or(0.0) + v);
+        }
+    }
+
+    Row::new_bar(
+        last.time,
+        first.open.unwrap_or(first.price),
+        high,
+        low,
+        last.price,
+        volume,
+    )
+}
@@ -0,0 +1,361 @@
+use super::*;
+use chrono::DateTime;
+use serde_json::Value;
+use std::io::BufRead;
+
+/// Anything a price history can be read from.
+pub trait PriceSource {
+    /// Reads all rows in the order of the source.
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError>;
+}
+
+/// The headerless TSV format of `bitcoin_price_hist_since_first_10000.tsv`, see `load`.
+pub struct TsvSource<R: io::Read> {
+    reader: Option<R>,
+}
+
+impl<R: io::Read> TsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+        }
+    }
+}
+
+impl<R: io::Read> PriceSource for TsvSource<R> {
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError> {
+        match self.reader.take() {
+            Some(reader) => load_from_reader(reader),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+/// Column names recognized by `CsvSource` and `JsonLinesSource`, case insensitive.
+const TIME_KEYS: [&str; 5] = ["date", "time", "timestamp", "datetime", "snapped_at"];
+const CLOSE_KEYS: [&str; 3] = ["close", "price", "adj close"];
+const OPEN_KEYS: [&str; 1] = ["open"];
+const HIGH_KEYS: [&str; 1] = ["high"];
+const LOW_KEYS: [&str; 1] = ["low"];
+const VOLUME_KEYS: [&str; 2] = ["volume", "total_volume"];
+
+/// A comma separated file with a header line, e.g. "Date,Open,High,Low,Close,Volume".
+/// Times are dates in one of the accepted formats or unix timestamps in seconds or
+/// milliseconds.
+pub struct CsvSource<R: io::Read> {
+    reader: Option<R>,
+    delimiter: u8,
+}
+
+impl<R: io::Read> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+            delimiter: b',',
+        }
+    }
+
+    pub fn with_delimiter(reader: R, delimiter: u8) -> Self {
+        Self {
+            reader: Some(reader),
+            delimiter,
+        }
+    }
+}
+
+fn find_column(headers: &csv::StringRecord, keys: &[&str]) -> Option<usize> {
+    headers
+        .iter()
+        .position(|header| keys.contains(&header.trim().to_lowercase().as_str()))
+}
+
+impl<R: io::Read> PriceSource for CsvSource<R> {
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError> {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return Ok(vec![]),
+        };
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(self.delimiter)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .from_reader(reader);
+
+        let headers = rdr.headers()?.clone();
+        let missing = |name: &str| LoadError::Parse {
+            line: 1,
+            message: format!("no {} column in header {:?}", name, headers),
+        };
+        let time_column = find_column(&headers, &TIME_KEYS).ok_or_else(|| missing("date"))?;
+        let close_column = find_column(&headers, &CLOSE_KEYS).ok_or_else(|| missing("close"))?;
+        let open_column = find_column(&headers, &OPEN_KEYS);
+        let high_column = find_column(&headers, &HIGH_KEYS);
+        let low_column = find_column(&headers, &LOW_KEYS);
+        let volume_column = find_column(&headers, &VOLUME_KEYS);
+
+        let mut rows = vec![];
+        for result in rdr.records() {
+            let record = result?;
+            let line = record.position().map(|p| p.line()).unwrap_or(0);
+            let field = |column: usize| record.get(column).unwrap_or("").trim();
+            let optional = |column: Option<usize>| -> Result<Option<f64>, LoadError> {
+                match column.map(field) {
+                    None | Some("") => Ok(None),
+                    Some(text) => parse_number(text, line).map(Some),
+                }
+            };
+
+            let time = parse_time_text(field(time_column), line)?;
+            let mut row = Row::new(time, parse_number(field(close_column), line)?);
+            row.open = optional(open_column)?;
+            row.high = optional(high_column)?;
+            row.low = optional(low_column)?;
+            row.volume = optional(volume_column)?;
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+}
+
+/// One JSON object per line with the same keys as `CsvSource`, e.g.
+/// `{"time": 1612828800000, "open": 38871.4, "close": 46375.9}`. Blank lines are skipped.
+pub struct JsonLinesSource<R: io::Read> {
+    reader: Option<R>,
+}
+
+impl<R: io::Read> JsonLinesSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+        }
+    }
+}
+
+fn find_key<'a>(object: &'a serde_json::Map<String, Value>, keys: &[&str]) -> Option<&'a Value> {
+    object
+        .iter()
+        .find(|(key, _)| keys.contains(&key.to_lowercase().as_str()))
+        .map(|(_, value)| value)
+}
+
+impl<R: io::Read> PriceSource for JsonLinesSource<R> {
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError> {
+        let reader = match self.reader.take() {
+            Some(reader) => io::BufReader::new(reader),
+            None => return Ok(vec![]),
+        };
+
+        let mut rows = vec![];
+        for (index, text) in reader.lines().enumerate() {
+            let text = text?;
+            let line = index as u64 + 1;
+            if text.trim().is_empty() {
+                continue;
+            }
+            let value: Value = serde_json::from_str(&text).map_err(|e| LoadError::Parse {
+                line,
+                message: e.to_string(),
+            })?;
+            let object = value.as_object().ok_or_else(|| LoadError::Parse {
+                line,
+                message: "not a JSON object".to_string(),
+            })?;
+            let field = |keys: &[&str], name: &str| {
+                find_key(object, keys).ok_or_else(|| LoadError::Parse {
+                    line,
+                    message: format!("no {} field", name),
+                })
+            };
+            let optional = |keys: &[&str]| -> Result<Option<f64>, LoadError> {
+                match find_key(object, keys) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(value) => json_number(value, line).map(Some),
+                }
+            };
+
+            let time = json_time(field(&TIME_KEYS, "date")?, line)?;
+            let mut row = Row::new(time, json_number(field(&CLOSE_KEYS, "close")?, line)?);
+            row.open = optional(&OPEN_KEYS)?;
+            row.high = optional(&HIGH_KEYS)?;
+            row.low = optional(&LOW_KEYS)?;
+            row.volume = optional(&VOLUME_KEYS)?;
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+}
+
+/// Binance kline dumps from data.binance.vision: open time, open, high, low, close, volume,
+/// close time, ... without header. Rows are stamped with the close time, like the daily
+/// closes in the bundled TSV.
+pub struct BinanceKlineSource<R: io::Read> {
+    reader: Option<R>,
+}
+
+impl<R: io::Read> BinanceKlineSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+        }
+    }
+}
+
+impl<R: io::Read> PriceSource for BinanceKlineSource<R> {
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError> {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return Ok(vec![]),
+        };
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+
+        let mut rows = vec![];
+        for result in rdr.records() {
+            let record = result?;
+            let line = record.position().map(|p| p.line()).unwrap_or(0);
+            let field = |column: usize| record.get(column).unwrap_or("").trim();
+            // Newer dumps start with a header line.
+            if line == 1 && field(0).parse::<f64>().is_err() {
+                continue;
+            }
+            if record.len() < 7 {
+                return Err(LoadError::Parse {
+                    line,
+                    message: format!("expected at least 7 fields, got {}", record.len()),
+                });
+            }
+
+            let time = epoch_to_time(parse_number(field(6), line)?, line)?;
+            rows.push(Row::new_bar(
+                time,
+                parse_number(field(1), line)?,
+                parse_number(field(2), line)?,
+                parse_number(field(3), line)?,
+                parse_number(field(4), line)?,
+                Some(parse_number(field(5), line)?),
+            ));
+        }
+
+        Ok(rows)
+    }
+}
+
+/// CoinGecko `market_chart` exports: `{"prices": [[ms, price], ...], "total_volumes": [[ms,
+/// volume], ...], ...}`. Volumes are quoted in the vs currency.
+pub struct CoinGeckoSource<R: io::Read> {
+    reader: Option<R>,
+}
+
+impl<R: io::Read> CoinGeckoSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Some(reader),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinGeckoChart {
+    prices: Vec<(f64, f64)>,
+    #[serde(default)]
+    total_volumes: Vec<(f64, f64)>,
+}
+
+impl<R: io::Read> PriceSource for CoinGeckoSource<R> {
+    fn read_rows(&mut self) -> Result<Vec<Row>, LoadError> {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return Ok(vec![]),
+        };
+        let chart: CoinGeckoChart =
+            serde_json::from_reader(reader).map_err(|e| LoadError::Parse {
+                line: e.line() as u64,
+                message: e.to_string(),
+            })?;
+
+        let mut rows = vec![];
+        for (i, (timestamp, price)) in chart.prices.iter().enumerate() {
+            let mut row = Row::new(epoch_to_time(*timestamp, 1)?, *price);
+            row.volume = chart
+                .total_volumes
+                .get(i)
+                .filter(|(volume_timestamp, _)| volume_timestamp == timestamp)
+                .map(|(_, volume)| *volume);
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+}
+
+fn parse_number(text: &str, line: u64) -> Result<f64, LoadError> {
+    text.parse().map_err(|_| LoadError::Parse {
+        line,
+        message: format!("invalid number {:?}", text),
+    })
+}
+
+/// Unix timestamps in seconds, milliseconds or microseconds, told apart by magnitude.
+fn epoch_to_time(timestamp: f64, line: u64) -> Result<NaiveDateTime, LoadError> {
+    let millis = if timestamp.abs() >= 1e14 {
+        timestamp / 1000.0
+    } else if timestamp.abs() >= 1e11 {
+        timestamp
+    } else {
+        timestamp * 1000.0
+    };
+    DateTime::from_timestamp_millis(millis as i64)
+        .map(|time| time.naive_utc())
+        .ok_or_else(|| LoadError::Parse {
+            line,
+            message: format!("invalid timestamp {}", timestamp),
+        })
+}
+
+fn parse_time_text(text: &str, line: u64) -> Result<NaiveDateTime, LoadError> {
+    if let Ok(timestamp) = text.parse::<f64>() {
+        return epoch_to_time(timestamp, line);
+    }
+    parse_date_time(text)
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|t| t.naive_utc())
+        })
+        .or_else(|| {
+            // CoinGecko CSV: "2021-02-09 00:00:00 UTC".
+            parse_date_time(text.trim_end_matches("UTC").trim())
+        })
+        .ok_or_else(|| LoadError::Parse {
+            line,
+            message: format!("invalid date {:?}", text),
+        })
+}
+
+fn json_number(value: &Value, line: u64) -> Result<f64, LoadError> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| LoadError::Parse {
+        line,
+        message: format!("invalid number {}", value),
+    })
+}
+
+fn json_time(value: &Value, line: u64) -> Result<NaiveDateTime, LoadError> {
+    match value {
+        Value::Number(_) => epoch_to_time(json_number(value, line)?, line),
+        Value::String(text) => parse_time_text(text.trim(), line),
+        _ => Err(LoadError::Parse {
+            line,
+            message: format!("invalid date {}", value),
+        }),
+    }
+}
@@ -0,0 +1,59 @@
+use super::*;
+
+/// A problem found in a price history. `index` is the row where it is detected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PriceIssue {
+    /// `count` intervals are missing between rows `index - 1` and `index`.
+    Missing {
+        index: usize,
+        count: i64,
+    },
+    /// The row falls into the same interval as the previous row.
+    Duplicate {
+        index: usize,
+    },
+    /// The row time is not after the previous row.
+    NonIncreasingTime {
+        index: usize,
+    },
+    NonPositivePrice {
+        index: usize,
+    },
+}
+
+/// Checks that `rows` has exactly one row per `interval` with increasing times and positive
+/// prices.
+pub fn validate(rows: &[Row], interval: Interval) -> Vec<PriceIssue> {
+    let mut issues = vec![];
+    for i in 0..rows.len() {
+        let row = &rows[i];
+        let prices = [Some(row.price), row.open, row.high, row.low];
+        if prices
+            .iter()
+            .flatten()
+            .any(|price| *price <= 0.0 || price.is_nan())
+        {
+            issues.push(PriceIssue::NonPositivePrice { index: i });
+        }
+
+        if i == 0 {
+            continue;
+        }
+        let previous = &rows[i - 1];
+        if row.time <= previous.time {
+            issues.push(PriceIssue::NonIncreasingTime { index: i });
+            continue;
+        }
+        let gap = interval.bucket(row.time) - interval.bucket(previous.time);
+        if gap == 0 {
+            issues.push(PriceIssue::Duplicate { index: i });
+        } else if gap > 1 {
+            issues.push(PriceIssue::Missing {
+                index: i,
+                count: gap - 1,
+            });
+        }
+    }
+
+    issues
+}
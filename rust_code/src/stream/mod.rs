@@ -0,0 +1,340 @@
+use super::amm::{AMMGrid, AMMSellBull, BondingCurve, CurveAMM};
+use super::cost_average::*;
+use super::price_data::LoadError;
+use super::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What happened on one tick of a `StreamDriver` or a `LadderDriver`.
+#[derive(Clone, Debug)]
+pub struct TickReport {
+    pub tick: usize,
+    pub date: String,
+    pub price: f64,
+    pub new_round: bool,
+    /// Orders filled on this tick.
+    pub orders: BuyLogs,
+    /// Total invested cash and coins after this tick, the cash and coins held by a ladder.
+    pub cash_invested: f64,
+    pub coins_invested: f64,
+}
+
+/// Feeds a `CostAverageMethodTrait` one bar at a time, for prices which are not known up front.
+/// Rounds start like in `dollar_cost_average`, but the ticks of a calendar round can only be
+/// estimated: the days from the bar to the next payday, assuming daily bars.
+pub struct StreamDriver<M: CostAverageMethodTrait> {
+    method: M,
+    round_schedule: RoundSchedule,
+    round_ticks: usize,
+    last_payday: Option<NaiveDate>,
+    tick: usize,
+}
+
+impl<M: CostAverageMethodTrait> StreamDriver<M> {
+    /// A new round every `ticks_per_round` bars.
+    pub fn new(method: M, supply: f64, ticks_per_round: usize) -> Self {
+        Self::with_schedule(method, supply, RoundSchedule::FixedTicks(ticks_per_round))
+    }
+
+    pub fn with_schedule(mut method: M, supply: f64, round_schedule: RoundSchedule) -> Self {
+        method.set_supply(supply);
+        Self {
+            method,
+            round_schedule,
+            round_ticks: 0,
+            last_payday: None,
+            tick: 0,
+        }
+    }
+
+    /// Returns the ticks of the round if `bar` starts one.
+    fn new_round(&mut self, bar: &Row) -> Option<usize> {
+        if let RoundSchedule::FixedTicks(ticks_per_round) = self.round_schedule {
+            let new_round = self.round_ticks == 0;
+            self.round_ticks += 1;
+            if self.round_ticks == ticks_per_round {
+                self.round_ticks = 0;
+            }
+            return if new_round {
+                Some(ticks_per_round)
+            } else {
+                None
+            };
+        }
+
+        let payday = self.round_schedule.payday_on_or_before(bar.day());
+        if self.tick > 0 && payday == self.last_payday {
+            return None;
+        }
+        self.last_payday = payday;
+        let next_payday = self.round_schedule.next_payday(bar.day())?;
+        Some((next_payday - bar.day()).num_days().max(1) as usize)
+    }
+
+    pub fn push(&mut self, bar: &Row) -> TickReport {
+        let ticks = self.new_round(bar);
+        let new_round = ticks.is_some();
+        if let Some(ticks) = ticks {
+            self.method.start_new_round(ticks);
+        }
+
+        self.method.feed_bar(bar);
+        let (cash_invested, coins_invested) = self.method.get_invest_status();
+        let report = TickReport {
+            tick: self.tick,
+            date: bar.date.clone(),
+            price: bar.price,
+            new_round,
+            orders: self.method.take_orders(),
+            cash_invested,
+            coins_invested,
+        };
+        self.tick += 1;
+
+        report
+    }
+
+    /// Pushes every bar of `feed` until it ends, e.g. a `Vec<Row>`, a `Receiver<Row>` or
+    /// `replay`.
+    pub fn run<I: IntoIterator<Item = Row>>(&mut self, feed: I, on_tick: impl FnMut(&TickReport)) {
+        run_feed(feed, |bar| self.push(bar), on_tick);
+    }
+
+    /// Like `run` for a feed that can fail, e.g. a `TailFeed`. Stops at the first error.
+    pub fn try_run<I: IntoIterator<Item = io::Result<Row>>>(
+        &mut self,
+        feed: I,
+        on_tick: impl FnMut(&TickReport),
+    ) -> io::Result<()> {
+        try_run_feed(feed, |bar| self.push(bar), on_tick)
+    }
+
+    pub fn method(&self) -> &M {
+        &self.method
+    }
+
+    pub fn into_method(self) -> M {
+        self.method
+    }
+}
+
+fn run_feed<I: IntoIterator<Item = Row>>(
+    feed: I,
+    mut push: impl FnMut(&Row) -> TickReport,
+    mut on_tick: impl FnMut(&TickReport),
+) {
+    for bar in feed {
+        on_tick(&push(&bar));
+    }
+}
+
+fn try_run_feed<I: IntoIterator<Item = io::Result<Row>>>(
+    feed: I,
+    mut push: impl FnMut(&Row) -> TickReport,
+    mut on_tick: impl FnMut(&TickReport),
+) -> io::Result<()> {
+    for bar in feed {
+        on_tick(&push(&bar?));
+    }
+    Ok(())
+}
+
+/// An AMM ladder which fills its orders one bar at a time, see `LadderDriver`.
+pub trait BarLadder {
+    /// Fills the orders crossed by `bar`, logging them in `logs`.
+    fn feed_bar(&mut self, bar: &Row, order: IntrabarOrder, logs: &mut BuyLogs);
+    /// Returns (cash held, coins held).
+    fn holdings(&self) -> (f64, f64);
+}
+
+/// Logs the fill prices of the execution mode.
+impl BarLadder for AMMSellBull {
+    fn feed_bar(&mut self, bar: &Row, order: IntrabarOrder, logs: &mut BuyLogs) {
+        let mut fills = vec![];
+        self.execute_bar(bar, order, &mut fills);
+        logs.extend(fills.iter().map(|fill| (fill.fill_price, fill.coins)));
+    }
+    fn holdings(&self) -> (f64, f64) {
+        (self.cash, self.coins)
+    }
+}
+
+impl BarLadder for AMMGrid {
+    fn feed_bar(&mut self, bar: &Row, order: IntrabarOrder, logs: &mut BuyLogs) {
+        self.update_bar(bar, order, logs);
+    }
+    fn holdings(&self) -> (f64, f64) {
+        (self.cash, self.coins)
+    }
+}
+
+impl<C: BondingCurve> BarLadder for CurveAMM<C> {
+    fn feed_bar(&mut self, bar: &Row, order: IntrabarOrder, logs: &mut BuyLogs) {
+        self.update_bar(bar, order, logs);
+    }
+    fn holdings(&self) -> (f64, f64) {
+        (self.cash, self.coins)
+    }
+}
+
+/// Feeds an AMM ladder one bar at a time like `StreamDriver` feeds a cost average method, e.g.
+/// the `AMMSellBull` of `ExitLadder::ladder`, an `AMMGrid` or a `CurveAMM`. Ladders have no
+/// rounds.
+pub struct LadderDriver<L: BarLadder> {
+    ladder: L,
+    intrabar_order: IntrabarOrder,
+    tick: usize,
+}
+
+impl<L: BarLadder> LadderDriver<L> {
+    pub fn new(ladder: L, intrabar_order: IntrabarOrder) -> Self {
+        Self {
+            ladder,
+            intrabar_order,
+            tick: 0,
+        }
+    }
+
+    pub fn push(&mut self, bar: &Row) -> TickReport {
+        let mut orders = vec![];
+        self.ladder.feed_bar(bar, self.intrabar_order, &mut orders);
+        let (cash, coins) = self.ladder.holdings();
+        let report = TickReport {
+            tick: self.tick,
+            date: bar.date.clone(),
+            price: bar.price,
+            new_round: false,
+            orders,
+            cash_invested: cash,
+            coins_invested: coins,
+        };
+        self.tick += 1;
+
+        report
+    }
+
+    /// See `StreamDriver::run`.
+    pub fn run<I: IntoIterator<Item = Row>>(&mut self, feed: I, on_tick: impl FnMut(&TickReport)) {
+        run_feed(feed, |bar| self.push(bar), on_tick);
+    }
+
+    /// See `StreamDriver::try_run`.
+    pub fn try_run<I: IntoIterator<Item = io::Result<Row>>>(
+        &mut self,
+        feed: I,
+        on_tick: impl FnMut(&TickReport),
+    ) -> io::Result<()> {
+        try_run_feed(feed, |bar| self.push(bar), on_tick)
+    }
+
+    pub fn ladder(&self) -> &L {
+        &self.ladder
+    }
+
+    pub fn into_ladder(self) -> L {
+        self.ladder
+    }
+}
+
+/// Yields `rows` with a pause of `interval` before every bar but the first, to replay a history
+/// at a chosen speed.
+pub fn replay(rows: Vec<Row>, interval: Duration) -> impl Iterator<Item = Row> {
+    rows.into_iter().enumerate().map(move |(i, row)| {
+        if i > 0 {
+            thread::sleep(interval);
+        }
+        row
+    })
+}
+
+/// Follows a TSV price file in the format of `price_data::load`, yielding rows as lines are
+/// appended. A line which can not be parsed is yielded as an `io::ErrorKind::InvalidData` error
+/// holding a `LoadError::Parse`, and the feed goes on. Ends after `idle_timeout` without new
+/// lines, with the last line if it has no line break, or after yielding a read error.
+pub struct TailFeed {
+    reader: BufReader<File>,
+    partial: String,
+    /// Lines read, for the errors.
+    line: u64,
+    failed: bool,
+    poll_interval: Duration,
+    idle_timeout: Duration,
+}
+
+impl TailFeed {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        poll_interval: Duration,
+        idle_timeout: Duration,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+            partial: String::new(),
+            line: 0,
+            failed: false,
+            poll_interval,
+            idle_timeout,
+        })
+    }
+}
+
+impl TailFeed {
+    /// Parses the buffered line. Returns `None` for comments and blank lines.
+    fn parse_line(&mut self) -> Option<io::Result<Row>> {
+        let line = std::mem::take(&mut self.partial);
+        if line.is_empty() {
+            return None;
+        }
+        self.line += 1;
+        match price_data::load_from_reader(line.as_bytes()) {
+            Ok(mut rows) => rows.pop().map(Ok),
+            Err(LoadError::Io(e)) => Some(Err(e)),
+            Err(LoadError::Parse { message, .. }) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                LoadError::Parse {
+                    line: self.line,
+                    message,
+                },
+            ))),
+        }
+    }
+}
+
+impl Iterator for TailFeed {
+    type Item = io::Result<Row>;
+
+    fn next(&mut self) -> Option<io::Result<Row>> {
+        if self.failed {
+            return None;
+        }
+        let mut idle_since = Instant::now();
+        loop {
+            match self.reader.read_line(&mut self.partial) {
+                Ok(0) => {
+                    if idle_since.elapsed() >= self.idle_timeout {
+                        // The last line of a finished file may have no line break.
+                        return self.parse_line();
+                    }
+                    thread::sleep(self.poll_interval);
+                }
+                Ok(_) => {
+                    // Wait for the writer to finish the line.
+                    if !self.partial.ends_with('\n') {
+                        continue;
+                    }
+                    idle_since = Instant::now();
+                    if let Some(row) = self.parse_line() {
+                        return Some(row);
+                    }
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
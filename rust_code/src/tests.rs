@@ -8,37 +8,23 @@
 use super::amm::*;
 use super::cost_average::*;
 use super::*;
-use cost_average::fluctuation::Fluctuation;
 
 #[test]
 fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
     let path = std::env::current_dir()?;
     println!("The current directory is {}", path.display());
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(b'\t')
-        .double_quote(false)
-        .escape(Some(b'\\'))
-        .flexible(true)
-        .comment(Some(b'#'))
-        .from_path("./src/bitcoin_price_hist_since_first_10000.tsv")?;
-    let mut rows = Vec::<Row>::new();
-    for result in rdr.records() {
-        let record = result?;
-        let row = record.deserialize(None)?;
-        rows.push(row);
-    }
+    let rows = price_data::load("./src/bitcoin_price_hist_since_first_10000.tsv")?;
 
     // Find the first date when BTC hits 10000;
     let (first_10000_date, enter_price, enter_index) = {
         let mut date = "Not Found".to_string();
         let mut price = 0.0;
         let mut index = 0;
-        for i in 0..rows.len() {
-            if rows[i].price >= 10000.0 {
-                date = rows[i].date.clone();
-                price = rows[i].price;
+        for (i, row) in rows.iter().enumerate() {
+            if row.price >= 10000.0 {
+                date = row.date.clone();
+                price = row.price;
                 index = i;
                 break;
             }
@@ -52,8 +38,8 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
     let (lowest_price, lowest_date, lowest_date_index) = {
         let mut index = 0;
         let mut lowest_price = rows[index].price;
-        for i in 0..rows.len() {
-            let price = rows[i].price;
+        for (i, row) in rows.iter().enumerate() {
+            let price = row.price;
             if price < lowest_price {
                 lowest_price = price;
                 index = i;
@@ -160,6 +146,8 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
     }
     bull_market_index += 1;
 
+    let round_schedule = RoundSchedule::FixedTicks(DAYS_PER_ROUND);
+    let supply_schedule = SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0);
     // Try normal dollar average.
     println!("\n Try normal dollar average:");
     let (_total_bear_invested_cash, _dca_coins, _) = dollar_cost_average(
@@ -171,6 +159,8 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
         begin_total_asset,
         &mut DollarCostAverage::default(),
         &rows,
+        &round_schedule,
+        &supply_schedule,
     );
     // Try daily dollar average.
     println!("\n Try daily dollar average:");
@@ -183,6 +173,8 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
         begin_total_asset,
         &mut DailyDollarCostAverage::default(),
         &rows,
+        &round_schedule,
+        &supply_schedule,
     );
     // Try AMM dollar average.
     println!("\n Try AMM dollar average:");
@@ -195,10 +187,12 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
         begin_total_asset,
         &mut AMMCostAverage::new(0.75, 0.9, REBALANCE_PERCENT_STEPS, 150),
         &rows,
+        &round_schedule,
+        &supply_schedule,
     );
     // Try AMM dollar average auto adjust.
     println!("\n Try AMM dollar average auto adjust:");
-    let (_total_bear_invested_cash, amm_coins_2, _) = dollar_cost_average(
+    let (_total_bear_invested_cash, _amm_coins_2, _) = dollar_cost_average(
         bull_market_index,
         exit_index,
         coins,
@@ -207,11 +201,13 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
         begin_total_asset,
         &mut AMMCostAverageAuto::new(0.605, 0.9, REBALANCE_PERCENT_STEPS, 150, 1.0 / 10.0),
         &rows,
+        &round_schedule,
+        &supply_schedule,
     );
     coins = amm_coins;
     println!("\nUse AMM dollar average\n");
 
-    // Fluctuation::new(&rows[exit_index..bull_market_index], &[15, 30, 60, 90, 120, 150]).log();
+    // fluctuation::Fluctuation::new(&rows[exit_index..bull_market_index], &[15, 30, 60, 90, 120, 150]).log();
 
     // Rebalance to 1/4 cash, 3/4 coins.
     let bull_start_price = rows[bull_market_index].price;
@@ -240,14 +236,12 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
         start_cash + start_coins * bull_start_price
     );
 
-    let mut exit_index = bull_market_index;
-    while exit_index < rows.len() {
-        if rows[exit_index].date.starts_with("1/12/2021") {
-            break;
-        }
-
-        exit_index += 1;
-    }
+    let mut exit_index = bull_market_index
+        + price_data::index_on_or_after(
+            &rows[bull_market_index..],
+            NaiveDate::from_ymd_opt(2021, 1, 12).unwrap(),
+        )
+        .unwrap_or(rows.len() - bull_market_index);
     while exit_index < rows.len() {
         exit_insane_bull(
             EXIT_CASH_RATIO,
@@ -284,3 +278,1893 @@ fn test_tsv_file_read() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_price_data_load_from_reader() {
+    let data = "# comment\n12/1/2017 23:58:00\t10869.84\n12/2/2017 23:58:00\t10912.7\n";
+    let rows = price_data::load_from_reader(data.as_bytes()).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].date, "12/2/2017 23:58:00");
+    assert_eq!(rows[1].price, 10912.7);
+
+    let data = "12/1/2017 23:58:00\t10869.84\n12/2/2017 23:58:00\tnot_a_price\n";
+    match price_data::load_from_reader(data.as_bytes()) {
+        Err(price_data::LoadError::Parse { line, .. }) => assert_eq!(line, 2),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    match price_data::load("./src/no_such_file.tsv") {
+        Err(price_data::LoadError::Io(_)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_row_date_lookup() {
+    let rows = load_history();
+    assert_eq!(
+        rows[0].time,
+        NaiveDate::from_ymd_opt(2017, 11, 1)
+            .unwrap()
+            .and_hms_opt(23, 58, 0)
+            .unwrap()
+    );
+
+    let day = NaiveDate::from_ymd_opt(2021, 1, 12).unwrap();
+    let index = price_data::index_on_or_after(&rows, day).unwrap();
+    assert!(rows[index].date.starts_with("1/12/2021"));
+    assert!(
+        price_data::index_on_or_after(&rows, NaiveDate::from_ymd_opt(2030, 1, 1).unwrap())
+            .is_none()
+    );
+
+    let december = price_data::slice_by_date(
+        &rows,
+        NaiveDate::from_ymd_opt(2017, 12, 1).unwrap(),
+        NaiveDate::from_ymd_opt(2018, 1, 1).unwrap(),
+    );
+    assert_eq!(december.len(), 31);
+    assert_eq!(december[0].date, "12/1/2017 23:58:00");
+
+    let row = Row::parse("2021-02-09", 46517.4).unwrap();
+    assert_eq!(row.day(), NaiveDate::from_ymd_opt(2021, 2, 9).unwrap());
+    assert_eq!(Row::new(rows[0].time, 1.0).date, rows[0].date);
+}
+
+#[test]
+fn test_ohlc_bar_fills() {
+    let data =
+        "2/8/2021 23:58:00\t46375.9\t38871.4\t46500\t38100\t120.5\n2/9/2021 23:58:00\t46517.4\n";
+    let rows = price_data::load_from_reader(data.as_bytes()).unwrap();
+    let bar = &rows[0];
+    assert_eq!(bar.open, Some(38871.4));
+    assert_eq!(bar.volume, Some(120.5));
+    assert_eq!(rows[1].high(), rows[1].price);
+    assert_eq!(
+        bar.intrabar_path(IntrabarOrder::HighFirst),
+        vec![38871.4, 46500.0, 38100.0, 46375.9]
+    );
+    assert_eq!(
+        bar.intrabar_path(IntrabarOrder::LowFirst),
+        vec![38871.4, 38100.0, 46500.0, 46375.9]
+    );
+    assert_eq!(
+        rows[1].intrabar_path(IntrabarOrder::HighFirst),
+        vec![46517.4]
+    );
+
+    // The sell ladder fills the levels touched by the high but not reached by the close.
+    let mut close_only = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    let mut with_high = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    let mut close_logs = vec![];
+    let mut bar_logs = vec![];
+    close_only.sell(bar.price, &mut close_logs);
+    with_high.sell_bar(bar, IntrabarOrder::LowFirst, &mut bar_logs);
+    assert!(bar_logs.len() > close_logs.len());
+    assert!(bar_logs.last().unwrap().0 < 46500.0);
+    assert!(bar_logs.last().unwrap().0 > bar.price);
+    assert!(with_high.cash > close_only.cash);
+
+    // The buy ladder fills down to the low.
+    let mut amm = AMMBuyBear::new(10000.0, 40000.0, 30, 0.75, 0.005);
+    let mut buy_logs = vec![];
+    amm.buy_bar(bar, IntrabarOrder::HighFirst, &mut buy_logs);
+    assert!(buy_logs.last().unwrap().0 >= 38100.0);
+    assert!(amm.last_price < 38100.0);
+
+    let mut close_method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    let mut bar_method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    for method in [&mut close_method, &mut bar_method].iter_mut() {
+        method.set_supply(2000.0);
+        method.start_new_round(DAYS_PER_ROUND);
+        method.feed_price(40000.0);
+    }
+    close_method.feed_price(bar.price);
+    bar_method.feed_bar(bar);
+    assert!(bar_method.get_invest_status().1 > close_method.get_invest_status().1);
+}
+
+#[test]
+fn test_price_data_validate_and_resample() {
+    let rows = load_history();
+    // One row per day, the strategies rely on it.
+    assert!(price_data::validate(&rows, price_data::Interval::Daily).is_empty());
+    let daily = price_data::resample(&rows, price_data::Interval::Daily);
+    assert_eq!(daily.len(), rows.len());
+    assert_eq!(daily.last().unwrap().date, rows.last().unwrap().date);
+
+    let data = "1/1/2021 10:00:00\t100\n\
+                1/1/2021 20:00:00\t110\n\
+                1/4/2021 10:00:00\t90\n\
+                1/3/2021 10:00:00\t95\n\
+                1/5/2021 10:00:00\t0\n";
+    let rows = price_data::load_from_reader(data.as_bytes()).unwrap();
+    assert_eq!(
+        price_data::validate(&rows, price_data::Interval::Daily),
+        vec![
+            price_data::PriceIssue::Duplicate { index: 1 },
+            price_data::PriceIssue::Missing { index: 2, count: 2 },
+            price_data::PriceIssue::NonIncreasingTime { index: 3 },
+            price_data::PriceIssue::NonPositivePrice { index: 4 },
+            // Compared with the out of order row before it.
+            price_data::PriceIssue::Missing { index: 4, count: 1 },
+        ]
+    );
+
+    let daily = price_data::resample(&rows[..3], price_data::Interval::Daily);
+    let closes: Vec<f64> = daily.iter().map(|row| row.price).collect();
+    assert_eq!(closes, vec![110.0, 110.0, 110.0, 90.0]);
+    assert_eq!(daily[0].open, Some(100.0));
+    assert_eq!(daily[0].high, Some(110.0));
+    assert_eq!(daily[0].low, Some(100.0));
+    assert_eq!(daily[1].date, "1/2/2021 20:00:00");
+
+    // 1/1/2021 is a Friday, 1/4/2021 a Monday.
+    let weekly = price_data::resample(&rows[..3], price_data::Interval::Weekly);
+    assert_eq!(weekly.len(), 2);
+    assert_eq!(weekly[0].price, 110.0);
+    assert_eq!(weekly[1].price, 90.0);
+}
+
+#[test]
+fn test_price_sources() {
+    use price_data::{
+        BinanceKlineSource, CoinGeckoSource, CsvSource, JsonLinesSource, PriceSource, TsvSource,
+    };
+
+    let tsv = "2/8/2021 23:58:00\t46375.9\n2/9/2021 23:58:00\t46517.4\n";
+    let rows = TsvSource::new(tsv.as_bytes()).read_rows().unwrap();
+    assert_eq!(rows.len(), 2);
+
+    let csv = "Date,Open,High,Low,Close,Volume\n\
+               2021-02-08,38871.4,46500,38100,46375.9,120.5\n\
+               2021-02-09,46375.9,48000,45000,46517.4,\n";
+    let rows = CsvSource::new(csv.as_bytes()).read_rows().unwrap();
+    assert_eq!(rows[0].day(), NaiveDate::from_ymd_opt(2021, 2, 8).unwrap());
+    assert_eq!(rows[0].price, 46375.9);
+    assert_eq!(rows[0].low, Some(38100.0));
+    assert_eq!(rows[0].volume, Some(120.5));
+    assert_eq!(rows[1].volume, None);
+
+    let bad_csv = "Date,Close\n2021-02-08,46375.9\n2021-02-09,oops\n";
+    match CsvSource::new(bad_csv.as_bytes()).read_rows() {
+        Err(price_data::LoadError::Parse { line, .. }) => assert_eq!(line, 3),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let json_lines = "{\"time\": 1612828800000, \"open\": 38871.4, \"close\": 46375.9}\n\n\
+                      {\"date\": \"2021-02-09T00:00:00Z\", \"price\": \"46517.4\"}\n";
+    let rows = JsonLinesSource::new(json_lines.as_bytes())
+        .read_rows()
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].day(), NaiveDate::from_ymd_opt(2021, 2, 9).unwrap());
+    assert_eq!(rows[0].open, Some(38871.4));
+    assert_eq!(rows[1].price, 46517.4);
+
+    let klines = "open_time,open,high,low,close,volume,close_time,quote_volume,count,\
+                  taker_buy_volume,taker_buy_quote_volume,ignore\n\
+                  1612742400000,38886.73,46203.93,38076.32,46196.46,123.4,1612828799999,0,0,0,0,0\n";
+    let rows = BinanceKlineSource::new(klines.as_bytes())
+        .read_rows()
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].date, "2/8/2021 23:59:59");
+    assert_eq!(rows[0].high, Some(46203.93));
+    assert_eq!(rows[0].price, 46196.46);
+
+    let gecko = "{\"prices\": [[1612742400000, 38903.4], [1612828800000, 46449.1]], \
+                 \"market_caps\": [], \"total_volumes\": [[1612742400000, 6.1e10], \
+                 [1612828800000, 9.3e10]]}";
+    let rows = CoinGeckoSource::new(gecko.as_bytes()).read_rows().unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].price, 46449.1);
+    assert_eq!(rows[1].volume, Some(9.3e10));
+}
+
+#[test]
+fn test_price_panel() {
+    use price_data::{Interval, MissingPolicy, PricePanel};
+
+    let btc = "1/1/2021 23:58:00\t29374.15\n1/2/2021 23:58:00\t32127.27\n\
+               1/3/2021 23:58:00\t32782.02\n1/4/2021 23:58:00\t31971.91\n";
+    // The FX series is stamped at midnight and misses 1/2 and 1/3.
+    let eur = "2021-01-01\t0.8187\n2021-01-04\t0.8155\n2021-01-05\t0.8143\n";
+    let mut panel = PricePanel::new(Interval::Daily);
+    panel.insert(
+        "BTC",
+        &price_data::load_from_reader(btc.as_bytes()).unwrap(),
+    );
+    panel.insert(
+        "EUR",
+        &price_data::load_from_reader(eur.as_bytes()).unwrap(),
+    );
+
+    assert_eq!(panel.symbols(), vec!["BTC", "EUR"]);
+    assert_eq!(panel.len(), 5);
+    assert_eq!(panel.price("BTC", 1), Some(32127.27));
+    assert_eq!(panel.price("EUR", 1), None);
+    assert_eq!(panel.price("BTC", 4), None);
+
+    let aligned = panel
+        .align(&["BTC", "EUR"], MissingPolicy::ForwardFill)
+        .unwrap();
+    assert_eq!(aligned[0].len(), 5);
+    assert_eq!(aligned[1].len(), 5);
+    let fx: Vec<f64> = aligned[1].iter().map(|row| row.price).collect();
+    assert_eq!(fx, vec![0.8187, 0.8187, 0.8187, 0.8155, 0.8143]);
+    assert_eq!(aligned[0][4].price, 31971.91);
+    for (btc, eur) in aligned[0].iter().zip(&aligned[1]) {
+        assert_eq!(btc.day(), eur.day());
+    }
+
+    let aligned = panel.align(&["BTC", "EUR"], MissingPolicy::Drop).unwrap();
+    assert_eq!(aligned[0].len(), 2);
+    assert_eq!(aligned[0][1].price, 31971.91);
+
+    assert_eq!(panel.rows("EUR").unwrap().len(), 5);
+    assert!(panel.align(&["ETH"], MissingPolicy::Drop).is_none());
+}
+
+#[test]
+fn test_generated_paths() {
+    use generator::*;
+
+    let start_time = NaiveDate::from_ymd_opt(2018, 6, 14)
+        .unwrap()
+        .and_hms_opt(23, 58, 0)
+        .unwrap();
+    let gbm = GbmParams {
+        drift: 0.3,
+        volatility: 0.8,
+    };
+    let paths = generate_paths(&gbm, 3, 7, 6302.31, start_time, 800);
+    assert_eq!(paths.len(), 3);
+    assert_eq!(paths[0].len(), 800);
+    assert_eq!(paths[0][0].date, "6/15/2018 23:58:00");
+    assert!(price_data::validate(&paths[0], price_data::Interval::Daily).is_empty());
+    // Same seed, same path.
+    let again = generate_paths(&gbm, 3, 7, 6302.31, start_time, 800);
+    assert_eq!(
+        paths[2].last().unwrap().price,
+        again[2].last().unwrap().price
+    );
+    assert!(paths[0].last().unwrap().price != paths[1].last().unwrap().price);
+
+    // The average daily log return is (drift - volatility^2 / 2) / 365.
+    let long = &generate_paths(&gbm, 1, 1, 1.0, start_time, 100_000)[0];
+    let average = long.last().unwrap().price.ln() / long.len() as f64;
+    let expected = (0.3 - 0.8 * 0.8 / 2.0) / DAYS_PER_YEAR;
+    assert!(
+        (average - expected).abs() < 0.0005,
+        "{} {}",
+        average,
+        expected
+    );
+
+    let jumps = JumpDiffusionParams {
+        gbm,
+        jump_intensity: 6.0,
+        jump_mean: -0.1,
+        jump_volatility: 0.1,
+    };
+    let regimes = RegimeSwitchingParams {
+        bull: GbmParams {
+            drift: 1.5,
+            volatility: 0.7,
+        },
+        bear: GbmParams {
+            drift: -0.8,
+            volatility: 0.9,
+        },
+        bull_mean_days: 400.0,
+        bear_mean_days: 500.0,
+    };
+    let mut paths = generate_paths(&jumps, 20, 100, 6302.31, start_time, 840);
+    paths.extend(generate_paths(&regimes, 20, 200, 6302.31, start_time, 840));
+
+    for rows in &paths {
+        assert!(rows.iter().all(|row| row.price > 0.0));
+
+        let (cash, coins) = run_method(&mut AMMCostAverage::new(0.75, 0.9, 0.005, 150), rows);
+        assert!(cash > 0.0 && coins > 0.0);
+
+        // The auto adjustment stays finite on jumps and regime changes.
+        let mut method = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 150, 0.01);
+        let (cash, coins) = run_method(&mut method, rows);
+        let supply = DOLLAR_COST_AVERAGE_SUPPLY.0 * (rows.len() / DAYS_PER_ROUND) as f64;
+        assert!(cash.is_finite() && coins.is_finite() && coins > 0.0);
+        // It tracks the supply, borrowing from later rounds, so a fall can make it spend more.
+        assert!(
+            cash > 0.5 * supply && cash < 1.5 * supply,
+            "{} of {}",
+            cash,
+            supply
+        );
+
+        let mut cash = 30000.0;
+        let mut coins = 90000.0 / 6302.31;
+        let mut price = 6302.31;
+        let mut sell_logs = vec![];
+        for row in rows {
+            exit_insane_bull(
+                0.25,
+                &mut cash,
+                &mut coins,
+                0.005,
+                &mut price,
+                row.price,
+                &mut sell_logs,
+            );
+        }
+        assert!(coins > 0.0);
+    }
+}
+
+#[test]
+fn test_block_bootstrap_distribution() {
+    use generator::{generate_paths, BlockBootstrap};
+
+    let rows = load_history();
+    let bear = price_data::slice_by_date(
+        &rows,
+        NaiveDate::from_ymd_opt(2018, 6, 14).unwrap(),
+        NaiveDate::from_ymd_opt(2020, 10, 24).unwrap(),
+    );
+    let bootstrap = BlockBootstrap::new(&rows, 30);
+    let paths = generate_paths(
+        &bootstrap,
+        40,
+        2021,
+        bear[0].price,
+        bear[0].time,
+        bear.len(),
+    );
+    assert_eq!(paths[0].len(), bear.len());
+    assert_eq!(paths[0][0].time, bear[1].time);
+
+    let round_schedule = RoundSchedule::FixedTicks(DAYS_PER_ROUND);
+    let supply_schedule = SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0);
+    let distribution = |paths: &[Vec<Row>]| {
+        cost_average_distribution(
+            paths,
+            DollarCostAverage::default,
+            &round_schedule,
+            &supply_schedule,
+        )
+    };
+    let dca = distribution(&paths);
+    let daily = cost_average_distribution(
+        &paths,
+        DailyDollarCostAverage::default,
+        &round_schedule,
+        &supply_schedule,
+    );
+    let amm = cost_average_distribution(
+        &paths,
+        || AMMCostAverage::new(0.75, 0.9, 0.005, 150),
+        &round_schedule,
+        &supply_schedule,
+    );
+    for distribution in [dca, daily, amm].iter() {
+        let coins = distribution.final_coins;
+        assert!(coins.p5 <= coins.p25 && coins.p25 <= coins.p50);
+        assert!(coins.p50 <= coins.p75 && coins.p75 <= coins.p95);
+        assert!(distribution.average_price.p5 > 0.0);
+    }
+    // Every round of the plain dollar cost average invests the same amount.
+    assert_eq!(dca.invested_cash.p5, dca.invested_cash.p95);
+
+    let again = distribution(&generate_paths(
+        &bootstrap,
+        40,
+        2021,
+        bear[0].price,
+        bear[0].time,
+        bear.len(),
+    ));
+    assert_eq!(again.final_coins.p50, dca.final_coins.p50);
+
+    // The schedules are passed through: a weekly supply of a quarter invests about as much.
+    let weekly = cost_average_distribution(
+        &paths,
+        DollarCostAverage::default,
+        &RoundSchedule::Weekly,
+        &SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0 / 4.0),
+    );
+    assert!(weekly.invested_cash.p50 != dca.invested_cash.p50);
+    assert!((weekly.invested_cash.p50 / dca.invested_cash.p50 - 1.0).abs() < 0.15);
+
+    // The last row is fed too: a one-row path invests one round. Empty paths are skipped.
+    let short = distribution(&[vec![], paths[3][..1].to_vec()]);
+    assert_eq!(short.invested_cash.p95, DOLLAR_COST_AVERAGE_SUPPLY.0);
+    assert_eq!(short.average_price.p95, paths[3][0].price);
+    assert!(short.final_coins.p5 > 0.0);
+
+    let samples = [1.0, f64::INFINITY, f64::NAN, f64::NEG_INFINITY, 3.0];
+    assert_eq!(Percentiles::new(&samples).p50, 2.0);
+    assert_eq!(
+        cost_average::distribution::percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 0.25),
+        2.0
+    );
+    assert_eq!(
+        cost_average::distribution::percentile(&[1.0, 2.0], 0.5),
+        1.5
+    );
+}
+
+#[test]
+fn test_fx_cost_average() {
+    let rows = load_history();
+    let rows = &rows[..300];
+
+    // A flat rate converts everything by the same factor.
+    let round_schedule = RoundSchedule::FixedTicks(DOLLAR_COST_AVERAGE_SUPPLY.1);
+    let report = fx_cost_average(
+        &mut DollarCostAverage::default(),
+        rows,
+        &rows_with_rate(rows, 0.8),
+        &round_schedule,
+        &SupplySchedule::new(1600.0),
+    )
+    .unwrap();
+    let usd = fx_cost_average(
+        &mut DollarCostAverage::default(),
+        rows,
+        &rows_with_rate(rows, 1.0),
+        &round_schedule,
+        &SupplySchedule::new(2000.0),
+    )
+    .unwrap();
+    let (usd_cash, usd_coins) = (usd.invested_usd, usd.coins);
+    assert!((report.invested_usd - usd_cash).abs() < 1e-6);
+    assert!((report.coins - usd_coins).abs() < 1e-9);
+    assert!((report.average_price_home / report.average_price_usd - 0.8).abs() < 1e-9);
+    assert!((report.profit_home / report.profit_usd - 0.8).abs() < 1e-9);
+
+    // With a strengthening home currency the same home supply buys more USD over time.
+    let rising: Vec<Row> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| Row::new(row.time, 0.9 - 0.1 * i as f64 / rows.len() as f64))
+        .collect();
+    let report = fx_cost_average(
+        &mut AMMCostAverage::new(0.75, 0.9, 0.005, 150),
+        rows,
+        &rising,
+        &round_schedule,
+        &SupplySchedule::new(1600.0),
+    )
+    .unwrap();
+    assert!(report.invested_usd > report.invested_home / 0.9);
+    assert!(report.invested_usd < report.invested_home / 0.8);
+    let last_rate = rising.last().unwrap().price;
+    assert!((report.value_home / report.value_usd - last_rate).abs() < 1e-9);
+
+    // A raise in the home currency follows the supply schedule.
+    let mut supply_schedule = SupplySchedule::new(1600.0);
+    supply_schedule.add_raise(5, 3200.0);
+    let report = fx_cost_average(
+        &mut DollarCostAverage::default(),
+        rows,
+        &rows_with_rate(rows, 0.8),
+        &round_schedule,
+        &supply_schedule,
+    )
+    .unwrap();
+    assert!((report.invested_home - (5.0 * 1600.0 + 5.0 * 3200.0)).abs() < 1e-6);
+
+    // A rate missing for a day is an error, aligning with a missing policy fills it.
+    let mut gappy = rows_with_rate(rows, 0.8);
+    gappy.remove(10);
+    let fx = |fx: &[Row]| {
+        fx_cost_average(
+            &mut DollarCostAverage::default(),
+            rows,
+            fx,
+            &round_schedule,
+            &SupplySchedule::new(1600.0),
+        )
+    };
+    assert_eq!(fx(&gappy).unwrap_err(), FxAlignmentError { index: 299 });
+    let mut shifted = gappy.clone();
+    shifted.push(Row::new(rows[299].time, 0.8));
+    assert_eq!(fx(&shifted).unwrap_err(), FxAlignmentError { index: 10 });
+    let mut panel = price_data::PricePanel::new(price_data::Interval::Daily);
+    panel.insert("BTC", rows);
+    panel.insert("EURUSD", &gappy);
+    let aligned = panel
+        .align(&["BTC", "EURUSD"], price_data::MissingPolicy::ForwardFill)
+        .unwrap();
+    let filled = fx(&aligned[1]).unwrap();
+    assert!((filled.invested_home - 10.0 * 1600.0).abs() < 1e-6);
+}
+
+fn rows_with_rate(rows: &[Row], rate: f64) -> Vec<Row> {
+    rows.iter().map(|row| Row::new(row.time, rate)).collect()
+}
+
+#[test]
+fn test_stream_driver() {
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use stream::{replay, LadderDriver, StreamDriver, TailFeed};
+
+    let rows = load_history();
+    let rows = &rows[200..500];
+
+    // Streaming gives the same result as feeding the whole history.
+    let batch = run_method(&mut AMMCostAverage::new(0.75, 0.9, 0.005, 150), rows);
+
+    let (sender, receiver) = mpsc::channel();
+    let feed = rows.to_vec();
+    let producer = std::thread::spawn(move || {
+        for row in feed {
+            sender.send(row).unwrap();
+        }
+    });
+    let mut driver = StreamDriver::new(
+        AMMCostAverage::new(0.75, 0.9, 0.005, 150),
+        DOLLAR_COST_AVERAGE_SUPPLY.0,
+        DAYS_PER_ROUND,
+    );
+    let mut reports = vec![];
+    driver.run(receiver, |report| reports.push(report.clone()));
+    producer.join().unwrap();
+
+    assert_eq!(reports.len(), rows.len());
+    assert!(reports[0].new_round && reports[DAYS_PER_ROUND].new_round);
+    assert!(!reports[1].new_round);
+    assert_eq!(driver.method().get_invest_status(), batch);
+    let coins: f64 = reports
+        .iter()
+        .flat_map(|report| report.orders.iter())
+        .map(|order| order.1)
+        .sum();
+    assert!((coins - reports.last().unwrap().coins_invested).abs() < 1e-9);
+
+    let mut driver = StreamDriver::new(DollarCostAverage::default(), 2000.0, DAYS_PER_ROUND);
+    let mut orders = 0;
+    driver.run(
+        replay(rows[..40].to_vec(), Duration::from_millis(1)),
+        |report| orders += report.orders.len(),
+    );
+    assert_eq!(orders, 2);
+
+    // Follow a file another process appends to.
+    let path = std::env::temp_dir().join(format!("tail_feed_{}.tsv", std::process::id()));
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(file, "{}\t{}", rows[0].date, rows[0].price).unwrap();
+    let writer_rows = rows[1..5].to_vec();
+    let writer = std::thread::spawn(move || {
+        for row in writer_rows {
+            std::thread::sleep(Duration::from_millis(5));
+            write!(file, "{}\t", row.date).unwrap();
+            file.flush().unwrap();
+            writeln!(file, "{}", row.price).unwrap();
+        }
+    });
+    let feed = TailFeed::open(&path, Duration::from_millis(1), Duration::from_millis(200)).unwrap();
+    let tailed: Vec<Row> = feed.collect::<std::io::Result<_>>().unwrap();
+    writer.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(tailed.len(), 5);
+    assert_eq!(tailed[4].price, rows[4].price);
+
+    // A read error, here a line which is not UTF-8, reaches the caller.
+    let path = std::env::temp_dir().join(format!("tail_feed_err_{}.tsv", std::process::id()));
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(file, "{}\t{}", rows[0].date, rows[0].price).unwrap();
+    file.write_all(b"\xff\xfe\n").unwrap();
+    let feed = TailFeed::open(&path, Duration::from_millis(1), Duration::from_millis(50)).unwrap();
+    let mut driver = StreamDriver::new(DollarCostAverage::default(), 2000.0, DAYS_PER_ROUND);
+    let mut ticks = 0;
+    let result = driver.try_run(feed, |_| ticks += 1);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(ticks, 1);
+
+    // A line which does not parse is an error and the feed goes on, up to a last line without a
+    // line break.
+    let path = std::env::temp_dir().join(format!("tail_feed_bad_{}.tsv", std::process::id()));
+    let mut file = std::fs::File::create(&path).unwrap();
+    writeln!(file, "{}\t{}", rows[0].date, rows[0].price).unwrap();
+    writeln!(file, "{}\tnot a price", rows[1].date).unwrap();
+    write!(file, "{}\t{}", rows[2].date, rows[2].price).unwrap();
+    let feed = TailFeed::open(&path, Duration::from_millis(1), Duration::from_millis(50)).unwrap();
+    let tailed: Vec<std::io::Result<Row>> = feed.collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(tailed.len(), 3);
+    let error = tailed[1].as_ref().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("line 2: "));
+    assert_eq!(tailed[2].as_ref().unwrap().price, rows[2].price);
+
+    // Ladders stream too.
+    let mut batch = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    for row in rows {
+        batch.sell_bar(row, IntrabarOrder::HighFirst, &mut vec![]);
+    }
+    let mut driver = LadderDriver::new(
+        AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005),
+        IntrabarOrder::HighFirst,
+    );
+    let mut sold = 0.0;
+    driver.run(rows.to_vec(), |report| {
+        sold -= report.orders.iter().map(|order| order.1).sum::<f64>()
+    });
+    let ladder = driver.into_ladder();
+    assert_eq!((ladder.cash, ladder.coins), (batch.cash, batch.coins));
+    assert!((2.0 - ladder.coins - sold).abs() < 1e-9);
+}
+
+#[test]
+fn test_fee_model() {
+    let fee_model = FeeModel {
+        maker_fee: 0.001,
+        taker_fee: 0.002,
+        fixed_fee: 0.1,
+        spread: 0.001,
+    };
+    assert!((fee_model.maker(1000.0) - 1.1).abs() < 1e-12);
+    assert!((fee_model.taker(1000.0) - 2.1).abs() < 1e-12);
+
+    // Ladder fills pay the maker fee on their notional, the value lost is exactly the fees.
+    let mut free = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    let mut paying = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    paying.set_fee_model(fee_model);
+    let mut logs = vec![];
+    free.sell(46000.0, &mut logs);
+    let free_levels = logs.len();
+    logs.clear();
+    paying.sell(46000.0, &mut logs);
+    assert_eq!(logs.len(), free_levels);
+    assert!(paying.fees_paid > 0.0);
+    let notional: f64 = logs.iter().map(|log| -log.0 * log.1).sum();
+    assert!(
+        (paying.fees_paid - fee_model.maker(notional) - 0.1 * (logs.len() - 1) as f64).abs() < 1e-6
+    );
+    let value = |amm: &AMMSellBull| amm.cash + amm.coins * 46000.0;
+    assert!(value(&paying) < value(&free));
+
+    // The same ladder through the free function.
+    let mut ladder = ExitLadder::new(0.25, 0.005);
+    ladder.set_fee_model(fee_model);
+    let (mut cash, mut coins, mut price) = (25000.0, 2.0, 40000.0);
+    let mut exit_logs = vec![];
+    let fees = ladder.exit(&mut cash, &mut coins, &mut price, 46000.0, &mut exit_logs);
+    assert_eq!(exit_logs, logs);
+    assert_eq!(
+        (cash, coins, fees),
+        (paying.cash, paying.coins, paying.fees_paid)
+    );
+
+    let mut amm = AMMBuyBear::new(10000.0, 40000.0, 30, 0.75, 0.005);
+    amm.set_fee_model(fee_model);
+    let mut logs = vec![];
+    let (cash, coins) = amm.buy(38000.0, &mut logs);
+    let at_limit: f64 = logs.iter().map(|log| log.0 * log.1).sum();
+    assert!((cash - at_limit - amm.fees_paid).abs() < 1e-6);
+    assert!(coins > 0.0);
+
+    let rows = load_history();
+    let rows = &rows[200..500];
+    let run = |method: &mut dyn CostAverageMethodTrait| {
+        let (cash, coins) = run_method(method, rows);
+        (cash, coins, method.get_fees_paid())
+    };
+    let mut free_dca = DollarCostAverage::default();
+    let mut dca = DollarCostAverage::default();
+    dca.set_fee_model(fee_model);
+    let (free_cash, free_coins, free_fees) = run(&mut free_dca);
+    let (cash, coins, fees) = run(&mut dca);
+    assert_eq!(free_fees, 0.0);
+    assert_eq!(cash, free_cash);
+    assert!(coins < free_coins);
+    // Fees and spread explain the missing coins at the purchase prices.
+    assert!(fees > 10.0 * (0.002 * 2000.0 + 0.1));
+
+    let mut amm = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    amm.set_fee_model(fee_model);
+    let (_cash, _coins, amm_fees) = run(&mut amm);
+    assert!(amm_fees > 0.0);
+
+    // Methods without fees only implement the required methods.
+    struct HoldCash(f64);
+    impl CostAverageMethodTrait for HoldCash {
+        fn set_supply(&mut self, amount: f64) {
+            self.0 += amount;
+        }
+        fn start_new_round(&mut self, _ticks: usize) {}
+        fn feed_price(&mut self, _price: f64) {}
+        fn get_invest_status(&self) -> (f64, f64) {
+            (self.0, 0.0)
+        }
+    }
+    let mut hold = HoldCash(0.0);
+    hold.set_fee_model(fee_model);
+    // The supply of the 10 rounds.
+    assert_eq!(run(&mut hold), (20000.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_exchange_rules() {
+    let rules = ExchangeRules {
+        price_tick: 0.01,
+        quantity_step: 0.00001,
+        min_notional: 10.0,
+    };
+    // The README ladder sells 112.5 USD at 10924.1892.
+    let (price, quantity, notional) = rules.round_sell(10924.1892, 112.5).unwrap();
+    assert!((price - 10924.19).abs() < 1e-9);
+    assert!((quantity - 0.01029).abs() < 1e-12);
+    assert!((notional - 10924.19 * 0.01029).abs() < 1e-9);
+    assert_eq!(rules.round_sell(10924.1892, 9.0), None);
+    assert!(ExchangeRules::default()
+        .round_sell(10924.1892, 112.5)
+        .is_some());
+    // Buys round down and never spend more than the level's cash.
+    let (price, _quantity, notional) = rules.round_buy(10924.1852, 112.5).unwrap();
+    assert!((price - 10924.18).abs() < 1e-9);
+    assert!(notional <= 112.5);
+    assert!((rules.round_sell_price(10924.18) - 10924.18).abs() < 1e-9);
+    // Fills pay the maker fee on the rounded notional, buys never spend more than the cash.
+    let fee_model = FeeModel {
+        maker_fee: 0.001,
+        fixed_fee: 0.5,
+        ..Default::default()
+    };
+    let (_price, coins, spent, fee) = rules.fill_buy(&fee_model, 10924.1852, 112.5).unwrap();
+    assert!(spent <= 112.5 && (spent - fee - coins * 10924.18).abs() < 1e-9);
+    let (_price, coins, received, fee) = rules.fill_sell(&fee_model, 10924.1892, 112.5).unwrap();
+    assert!((received + fee - coins * 10924.19).abs() < 1e-9);
+    assert_eq!(rules.fill_sell(&fee_model, 10924.1892, -112.5), None);
+    assert_eq!(
+        ExchangeRules::default().fill_buy(&fee_model, 100.0, 0.5),
+        None
+    );
+
+    let is_placeable = |log: &(f64, f64)| {
+        let ticks = log.0 / rules.price_tick;
+        let steps = log.1.abs() / rules.quantity_step;
+        (ticks - ticks.round()).abs() < 1e-6
+            && (steps - steps.round()).abs() < 1e-6
+            && log.0 * log.1.abs() >= rules.min_notional - 1e-9
+    };
+
+    // Levels under the minimum are merged, the coins not sold stay in the AMM.
+    let mut free = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    let mut ruled = AMMSellBull::new(25000.0, 2.0, 40000.0, 0.25, 0.005);
+    ruled.set_exchange_rules(ExchangeRules {
+        min_notional: 500.0,
+        ..rules
+    });
+    let mut free_logs = vec![];
+    let mut logs = vec![];
+    free.sell(46000.0, &mut free_logs);
+    ruled.sell(46000.0, &mut logs);
+    assert!(logs.len() < free_logs.len());
+    assert!(logs.iter().all(|log| log.0 * -log.1 >= 500.0 - 1e-9));
+    let value = |amm: &AMMSellBull| amm.cash + amm.coins * 46000.0;
+    assert!((value(&ruled) - value(&free)).abs() < 0.01 * value(&free));
+    assert!(ruled.coins >= free.coins);
+
+    let mut amm = AMMBuyBear::new(10000.0, 40000.0, 30, 0.75, 0.005);
+    amm.set_exchange_rules(rules);
+    let mut logs = vec![];
+    let (cash, coins) = amm.buy(38000.0, &mut logs);
+    assert!(!logs.is_empty() && logs.iter().all(is_placeable));
+    assert!(logs
+        .iter()
+        .all(|log| log.0 <= 40000.0 && log.0 >= 38000.0 * 0.995));
+    let at_limit: f64 = logs.iter().map(|log| log.0 * log.1).sum();
+    assert!((cash - at_limit).abs() < 1e-6);
+    assert!((coins - logs.iter().map(|log| log.1).sum::<f64>()).abs() < 1e-12);
+    assert!((amm.cash + cash - 10000.0).abs() < 1e-6);
+
+    // The README ladder of the first bull market, placeable as it is.
+    let mut ladder = ExitLadder::new(0.25, 0.005);
+    ladder.set_exchange_rules(rules);
+    let (mut cash, mut coins, mut price) = (30000.0, 90000.0 / 10869.84, 10869.84);
+    let mut logs = vec![];
+    ladder.exit(&mut cash, &mut coins, &mut price, 19600.0, &mut logs);
+    assert!(logs.len() > 100 && logs.iter().all(is_placeable));
+    assert!(logs.iter().all(|log| log.0 >= 10869.84));
+
+    let rows = load_history();
+    let mut method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    method.set_exchange_rules(rules);
+    assert!(run_method(&mut method, &rows[200..500]).1 > 0.0);
+    assert!(method.take_orders().iter().all(is_placeable));
+}
+
+#[test]
+fn test_ladder_planner() {
+    // The first bull market of README.txt: 90000 USD of BTC at 10869.84 and 30000 USD cash.
+    let enter_price = 10869.84;
+    let planner = LadderPlanner::new(30000.0, 90000.0 / enter_price, 0.25, 0.005);
+    let orders = planner.sell_orders(enter_price, 19600.0);
+    assert!((orders[0].price - 10924.1892).abs() < 1e-3);
+    assert!((orders.last().unwrap().price - 19580.0).abs() < 1.0);
+    assert!(orders.windows(2).all(|pair| pair[0].price < pair[1].price));
+    assert!(orders
+        .iter()
+        .all(|order| order.notional > 100.0 && order.notional < 180.0));
+
+    // Same orders as running exit_insane_bull through the prices.
+    let mut cash = 30000.0;
+    let mut coins = 90000.0 / enter_price;
+    let mut price = enter_price;
+    let mut sell_logs = vec![];
+    for i in 0..=100 {
+        exit_insane_bull(
+            0.25,
+            &mut cash,
+            &mut coins,
+            0.005,
+            &mut price,
+            enter_price + (19600.0 - enter_price) * i as f64 / 100.0,
+            &mut sell_logs,
+        );
+    }
+    assert_eq!(sell_logs.len(), orders.len());
+    for (log, order) in sell_logs.iter().zip(&orders) {
+        assert_eq!(log.0, order.price);
+        assert_eq!(-log.1, order.quantity);
+    }
+
+    let orders = planner.buy_orders(enter_price, 6000.0);
+    assert!(orders.windows(2).all(|pair| pair[0].price > pair[1].price));
+    assert!(orders.last().unwrap().price >= 6000.0);
+    let spent: f64 = orders.iter().map(|order| order.notional).sum();
+    assert!(spent > 0.0 && spent < 30000.0);
+
+    let mut planner = planner;
+    planner.set_exchange_rules(ExchangeRules {
+        price_tick: 0.01,
+        quantity_step: 0.0001,
+        min_notional: 10.0,
+    });
+    let orders = planner.sell_orders(enter_price, 19600.0);
+    assert!(orders.iter().all(|order| order.notional >= 10.0));
+    assert!((orders[0].price - 10924.19).abs() < 1e-6);
+}
+
+#[test]
+fn test_closed_form_ladder() {
+    let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0);
+
+    // A 10x move, starting off the ratio.
+    let mut amm = AMMSellBull::new(1000.0, 2.0, 10000.0, 0.25, 0.005);
+    let mut fast = AMMSellBull::new(1000.0, 2.0, 10000.0, 0.25, 0.005);
+    let mut logs = vec![];
+    let (cash, coins) = amm.sell(100000.0, &mut logs);
+    let (fast_cash, fast_coins) = fast.sell_fast(100000.0);
+    assert_eq!(logs.len(), closed_form::levels_up(10000.0, 100000.0, 0.005));
+    assert!(close(cash, fast_cash) && close(coins, fast_coins));
+    assert!(close(amm.cash, fast.cash) && close(amm.coins, fast.coins));
+    assert!(close(amm.last_price, fast.last_price));
+
+    // The bull exit of the README walking the history day by day.
+    let rows = load_history();
+    let exit = |fast_path: bool| {
+        let mut ladder = ExitLadder::new(0.25, 0.005);
+        ladder.set_fast_path(fast_path);
+        let (mut cash, mut coins, mut price) = (30000.0, 90000.0 / rows[0].price, rows[0].price);
+        let mut logs = vec![];
+        for row in &rows[..200] {
+            ladder.exit(&mut cash, &mut coins, &mut price, row.price, &mut logs);
+        }
+        (cash, coins, price, logs.len())
+    };
+    let (cash, coins, price, orders) = exit(false);
+    let (fast_cash, fast_coins, fast_price, fast_orders) = exit(true);
+    assert!(orders > 0 && fast_orders == 0);
+    assert!(close(cash, fast_cash) && close(coins, fast_coins) && close(price, fast_price));
+
+    // After the first level, the value follows the geometric formula.
+    let value = 1000.0 + 2.0 * 10000.0;
+    let (cash, coins) = closed_form::sell_fills(value, 0.25, 0.005, 10000.0, logs.len() - 1);
+    assert!(close(amm.cash, 0.25 * value + cash));
+    assert!(close(amm.coins, 0.75 * value / 10000.0 - coins));
+
+    // Starting with more cash than the ratio, nothing is sold until the coins are worth enough.
+    let mut amm = AMMSellBull::new(20000.0, 1.0, 10000.0, 0.5, 0.01);
+    let mut fast = AMMSellBull::new(20000.0, 1.0, 10000.0, 0.5, 0.01);
+    let mut logs = vec![];
+    amm.sell(25000.0, &mut logs);
+    fast.sell_fast(25000.0);
+    assert!(logs[0].0 >= 20000.0);
+    assert!(close(amm.cash, fast.cash) && close(amm.coins, fast.coins));
+
+    let mut amm = AMMBuyBear::new(10000.0, 40000.0, 30, 0.75, 0.005);
+    let mut fast = AMMBuyBear::new(10000.0, 40000.0, 30, 0.75, 0.005);
+    let mut logs = vec![];
+    let mut total = (0.0, 0.0);
+    let mut fast_total = (0.0, 0.0);
+    for &price in &[39000.0, 41000.0, 25000.0, 30000.0, 4000.0] {
+        let (cash, coins) = amm.buy(price, &mut logs);
+        let (fast_cash, fast_coins) = fast.buy_fast(price);
+        total = (total.0 + cash, total.1 + coins);
+        fast_total = (fast_total.0 + fast_cash, fast_total.1 + fast_coins);
+    }
+    assert!(close(total.0, fast_total.0) && close(total.1, fast_total.1));
+    assert!(close(amm.cash, fast.cash) && close(amm.coins, fast.coins));
+    let (cash, coins) = closed_form::buy_fills(10000.0, 0.75, 0.005, 40000.0, logs.len() - 1);
+    assert!(close(amm.cash, 0.75 * 10000.0 - cash));
+    assert!(close(amm.coins, 0.25 * 10000.0 / 40000.0 + coins));
+
+    // Small steps approach the continuous limit.
+    let k = closed_form::levels_up(10000.0, 20000.0, 0.0001);
+    let price = 10000.0 * 1.0001f64.powi(k as i32);
+    let discrete = closed_form::value_after_steps_up(1000.0, 0.25, 0.0001, k);
+    let continuous = closed_form::continuous_value(1000.0, 0.25, 10000.0, price);
+    assert!((discrete / continuous - 1.0).abs() < 1e-4);
+
+    let run = |method: &mut dyn CostAverageMethodTrait| run_method(method, &rows[200..800]);
+    let mut method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    let (cash, coins) = run(&mut method);
+    method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    method.set_fast_path(true);
+    let (fast_cash, fast_coins) = run(&mut method);
+    assert!((cash - fast_cash).abs() < 1e-6 * cash);
+    assert!((coins - fast_coins).abs() < 1e-6 * coins);
+
+    let mut method = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 150, 0.1);
+    let (cash, coins) = run(&mut method);
+    method = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 150, 0.1);
+    method.set_fast_path(true);
+    let (fast_cash, fast_coins) = run(&mut method);
+    assert!((cash - fast_cash).abs() < 1e-6 * cash);
+    assert!((coins - fast_coins).abs() < 1e-6 * coins);
+}
+
+#[test]
+fn test_amm_grid() {
+    let mut grid = AMMGrid::new(5000.0, 0.5, 10000.0, 0.5, 0.01);
+    let mut logs = vec![];
+    // Oscillate between the levels around 10000 and 11000.
+    for _ in 0..5 {
+        grid.update(11000.0, &mut logs);
+        grid.update(10000.0, &mut logs);
+    }
+    assert!(logs.iter().any(|log| log.1 > 0.0) && logs.iter().any(|log| log.1 < 0.0));
+    assert!(grid.grid_profit > 0.0);
+    assert!(grid.value(10000.0) > 10000.0);
+    let total = grid.value(10000.0) - 10000.0;
+    assert_eq!(grid.directional_pnl(10000.0), 0.0);
+    assert_eq!(grid.directional_pnl(11000.0), 500.0);
+    assert!(
+        (grid.directional_pnl(10000.0) + grid.grid_profit + grid.open_pnl(10000.0)
+            - grid.fees_paid
+            - total)
+            .abs()
+            < 1e-9
+    );
+    // Round trips back to the start price are mostly grid profit.
+    assert!(grid.open_pnl(10000.0).abs() < grid.grid_profit);
+
+    // One round trip on a 10% grid by hand: at 11000 the 2100 of value is rebalanced by selling
+    // 50 USD, at 10000 the 2004.55 of value by buying 47.73 USD, so 50 / 11000 coins are bought
+    // back 1000 cheaper.
+    let mut grid = AMMGrid::new(1000.0, 0.1, 10000.0, 0.5, 0.1);
+    let mut logs = vec![];
+    grid.update(11000.0, &mut logs);
+    assert!((grid.directional_pnl(11000.0) - 100.0).abs() < 1e-9);
+    grid.update(10000.0, &mut logs);
+    assert_eq!(logs.len(), 2);
+    assert!((grid.grid_profit - 50.0 / 11.0).abs() < 1e-9);
+    assert!(grid.open_pnl(10000.0).abs() < 1e-9);
+    assert!((grid.value(10000.0) - 2000.0 - 50.0 / 11.0).abs() < 1e-9);
+
+    // A one way move has no round trips.
+    let mut grid = AMMGrid::new(5000.0, 0.5, 10000.0, 0.5, 0.01);
+    let mut logs = vec![];
+    grid.update(20000.0, &mut logs);
+    assert!(logs.iter().all(|log| log.1 < 0.0));
+    assert_eq!(grid.grid_profit, 0.0);
+    assert!(grid.directional_pnl(20000.0) > 0.0);
+    assert!(grid.last_price() <= 20000.0 && grid.level_price(1) > 10000.0);
+
+    // The high and low of a bar are both traded.
+    let mut grid = AMMGrid::new(5000.0, 0.5, 10000.0, 0.5, 0.01);
+    grid.set_fee_model(FeeModel {
+        maker_fee: 0.001,
+        ..Default::default()
+    });
+    let bar = Row::new_bar(
+        NaiveDate::from_ymd_opt(2021, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+        10000.0,
+        10500.0,
+        9500.0,
+        10000.0,
+        None,
+    );
+    let mut logs = vec![];
+    grid.update_bar(&bar, IntrabarOrder::HighFirst, &mut logs);
+    assert!(logs[0].1 < 0.0 && logs.last().unwrap().1 < 0.0);
+    assert!(grid.grid_profit > 0.0 && grid.fees_paid > 0.0);
+}
+
+#[test]
+fn test_bonding_curves() {
+    let product = ConstantProduct::new(20000.0, 10000.0);
+    assert_eq!(product.reserves_at(10000.0), (10000.0, 1.0));
+    let (cash, coins) = product.reserves_at(40000.0);
+    assert!((cash * coins - 10000.0).abs() < 1e-6);
+    // Constant product is the constant ratio curve at one half.
+    let half = ConstantRatio::new(20000.0, 10000.0, 0.5);
+    assert!((half.value_at(40000.0) - product.value_at(40000.0)).abs() < 1e-6);
+
+    let range = Concentrated::new(20000.0, 10000.0, 8000.0, 12500.0);
+    assert!((range.value_at(10000.0) - 20000.0).abs() < 1e-6);
+    assert_eq!(range.reserves_at(7000.0).0, 0.0);
+    assert!(range.reserves_at(13000.0).1.abs() < 1e-12);
+    assert_eq!(range.reserves_at(13000.0), range.reserves_at(20000.0));
+
+    // The constant ratio curve selling on rise is the limit of exit_insane_bull.
+    let mut curve_amm = CurveAMM::new(
+        ConstantRatio::new(20000.0, 10000.0, 0.25),
+        10000.0,
+        CurveMode::SellOnRise,
+    );
+    let mut amm = AMMSellBull::new(5000.0, 1.5, 10000.0, 0.25, 0.0001);
+    let mut logs = vec![];
+    for &price in &[12000.0, 11000.0, 15000.0, 9000.0, 20000.0] {
+        curve_amm.update(price, &mut logs);
+        amm.sell(price, &mut vec![]);
+    }
+    assert_eq!(logs.len(), 3);
+    assert!(logs.iter().all(|log| log.1 < 0.0));
+    assert!((curve_amm.value(20000.0) / (amm.cash + amm.coins * 20000.0) - 1.0).abs() < 1e-3);
+
+    let mut curve_amm = CurveAMM::new(
+        ConstantProduct::new(20000.0, 10000.0),
+        10000.0,
+        CurveMode::BuyOnFall,
+    );
+    let mut logs = vec![];
+    curve_amm.update(8000.0, &mut logs);
+    curve_amm.update(9000.0, &mut logs);
+    curve_amm.update(5000.0, &mut logs);
+    assert_eq!(logs.len(), 2);
+    // Fills are between the prices traded through.
+    assert!(logs[0].0 < 10000.0 && logs[0].0 > 8000.0 && logs[0].1 > 0.0);
+
+    // Compare the curves as bull exits and bear buys on the same history.
+    let rows = load_history();
+    let start = rows[0].price;
+    let curves = || -> Vec<Box<dyn BondingCurve>> {
+        vec![
+            Box::new(ConstantProduct::new(20000.0, start)),
+            Box::new(ConstantRatio::new(20000.0, start, 0.25)),
+            Box::new(Concentrated::new(20000.0, start, start / 2.0, start * 2.0)),
+        ]
+    };
+    for &mode in &[CurveMode::SellOnRise, CurveMode::BuyOnFall, CurveMode::Both] {
+        for curve in curves() {
+            let mut curve_amm = CurveAMM::new(curve, start, mode);
+            let mut logs = vec![];
+            for row in &rows {
+                curve_amm.update_bar(row, IntrabarOrder::default(), &mut logs);
+            }
+            assert!(!logs.is_empty());
+            assert!(curve_amm.value(rows.last().unwrap().price) > 0.0);
+        }
+    }
+}
+
+#[test]
+fn test_range_position() {
+    let time = NaiveDate::from_ymd_opt(2021, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let mut position = RangePosition::new(20000.0, 10000.0, 8000.0, 12500.0, 0.003, 0.01);
+    position.set_assumed_volume(1000.0);
+    let mut bar = Row::new(time, 10000.0);
+    let report = position.push(&bar);
+    assert!(report.in_range);
+    assert!(report.impermanent_loss.abs() < 1e-12);
+    assert!((report.fees_earned - 1000.0 * 10000.0 * 0.003 * 0.01).abs() < 1e-9);
+
+    // Above the range, all cash and no fees.
+    bar.price = 15000.0;
+    bar.volume = Some(5000.0);
+    let report = position.push(&bar);
+    assert!(!report.in_range);
+    assert!(report.coins.abs() < 1e-12);
+    assert!(report.impermanent_loss < 0.0);
+    assert!((report.fees_earned - 300.0).abs() < 1e-9);
+
+    // Below the range, all coins.
+    bar.price = 6000.0;
+    let report = position.push(&bar);
+    assert!(report.cash.abs() < 1e-9 && report.coins > 0.0);
+    assert!(report.value < report.hold_value);
+
+    let rows = load_history();
+    let rows = &rows[..300];
+    let start = rows[0].price;
+    let mut position = RangePosition::new(20000.0, start, start * 0.8, start * 1.25, 0.003, 0.001);
+    position.set_assumed_volume(10000.0);
+    let reports = position.simulate(rows);
+    assert_eq!(reports.len(), rows.len());
+    assert!(reports.iter().any(|report| report.in_range));
+    assert!(reports.iter().any(|report| !report.in_range));
+    assert!(reports
+        .iter()
+        .all(|report| report.impermanent_loss <= 1e-12));
+    assert!(reports
+        .windows(2)
+        .all(|pair| pair[0].fees_earned <= pair[1].fees_earned));
+}
+
+#[test]
+fn test_cash_ratio_glide_path() {
+    let glide = CashRatioPath::piecewise_linear(vec![(33000.0, 0.25), (100000.0, 0.8)]).unwrap();
+    assert_eq!(glide.ratio_at(20000.0), 0.25);
+    assert_eq!(glide.ratio_at(200000.0), 0.8);
+    assert!((glide.ratio_at(66500.0) - 0.525).abs() < 1e-12);
+    assert_eq!(CashRatioPath::Fixed(0.25).ratio_at(66500.0), 0.25);
+    fn half(_price: f64) -> f64 {
+        0.5
+    }
+    assert_eq!(CashRatioPath::Custom(half).ratio_at(1.0), 0.5);
+
+    assert_eq!(
+        CashRatioPath::piecewise_linear(vec![]).unwrap_err(),
+        CashRatioPathError::NoPoints
+    );
+    assert_eq!(
+        CashRatioPath::piecewise_linear(vec![(1.0, 0.2), (3.0, 0.5), (2.0, 0.4)]).unwrap_err(),
+        CashRatioPathError::Unsorted { index: 2 }
+    );
+
+    let run = |path: &CashRatioPath| {
+        let mut ladder = ExitLadder::new(0.25, 0.005);
+        ladder.set_cash_ratio_path(path.clone());
+        let mut cash = 61458.03;
+        let mut coins = 184374.1 / 33000.0;
+        let mut price = 33000.0;
+        let mut sell_logs = vec![];
+        for i in 0..=100 {
+            ladder.exit(
+                &mut cash,
+                &mut coins,
+                &mut price,
+                33000.0 + 670.0 * i as f64,
+                &mut sell_logs,
+            );
+        }
+        (cash, coins, sell_logs)
+    };
+    let (fixed_cash, fixed_coins, fixed_logs) = run(&CashRatioPath::Fixed(0.25));
+    let (cash, coins, logs) = run(&glide);
+    assert!(cash > fixed_cash && coins < fixed_coins);
+    assert!((cash / (cash + coins * 100000.0) - 0.8).abs() < 0.01);
+    // Each level sells more than at the fixed ratio.
+    assert_eq!(logs.len(), fixed_logs.len());
+    assert!(logs[1..]
+        .iter()
+        .zip(&fixed_logs[1..])
+        .all(|(log, fixed)| log.1 < fixed.1));
+
+    // The path keeps the trading settings of the ladder.
+    let mut ladder = ExitLadder::new(0.25, 0.005);
+    ladder.set_cash_ratio_path(glide.clone());
+    ladder.set_fee_model(FeeModel {
+        maker_fee: 0.001,
+        ..FeeModel::default()
+    });
+    let (mut paying_cash, mut paying_coins, mut price) = (61458.03, 184374.1 / 33000.0, 33000.0);
+    let fees = ladder.exit(
+        &mut paying_cash,
+        &mut paying_coins,
+        &mut price,
+        100000.0,
+        &mut vec![],
+    );
+    assert!(fees > 0.0 && paying_cash < cash);
+
+    // The fixed path is exit_insane_bull.
+    let mut cash = 61458.03;
+    let mut coins = 184374.1 / 33000.0;
+    let mut price = 33000.0;
+    let mut sell_logs = vec![];
+    for i in 0..=100 {
+        exit_insane_bull(
+            0.25,
+            &mut cash,
+            &mut coins,
+            0.005,
+            &mut price,
+            33000.0 + 670.0 * i as f64,
+            &mut sell_logs,
+        );
+    }
+    assert_eq!((cash, coins), (fixed_cash, fixed_coins));
+    assert_eq!(sell_logs, fixed_logs);
+}
+
+#[test]
+fn test_reentry_ladder() {
+    let mut amm = AMMReentry::new(
+        25000.0,
+        75000.0 / 33000.0,
+        33000.0,
+        CashRatioPath::Fixed(0.25),
+        0.005,
+        0.1,
+        0.2,
+    );
+    let mut logs = vec![];
+    for i in 0..=20 {
+        amm.update(33000.0 + 650.0 * i as f64, &mut logs);
+    }
+    let sold = amm.report().coins_sold;
+    assert!(sold > 0.0 && logs.iter().all(|log| log.1 < 0.0));
+
+    // A small dip does not trigger the re-entry.
+    amm.update(44000.0, &mut logs);
+    assert!(!amm.is_reentering());
+    assert_eq!(amm.report().coins_bought_back, 0.0);
+
+    logs.clear();
+    for i in 0..=20 {
+        amm.update(46000.0 - 500.0 * i as f64, &mut logs);
+    }
+    assert!(amm.is_reentering());
+    assert!(logs.iter().all(|log| log.1 > 0.0 && log.0 <= 46000.0 * 0.9));
+    let report = amm.report();
+    assert!(report.recovered > 0.0 && report.recovered < 1.0);
+    assert!((report.cash_spent - logs.iter().map(|log| log.0 * log.1).sum::<f64>()).abs() < 1e-6);
+    let price = 36000.0;
+    let ratio = amm.cash() / (amm.cash() + amm.coins() * price);
+    assert!(ratio < 0.28);
+
+    // Deep enough, the ladder is back at the starting cash ratio.
+    amm.update(20000.0, &mut logs);
+    let ratio = amm.cash() / (amm.cash() + amm.coins() * 20000.0);
+    assert!((ratio - 0.25).abs() < 0.01);
+    assert!(amm.report().recovered > report.recovered);
+
+    // A new peak resumes the exit ladder.
+    logs.clear();
+    amm.update(47000.0, &mut logs);
+    assert!(!amm.is_reentering());
+    assert!(logs.iter().all(|log| log.1 < 0.0));
+
+    // With fees and exchange rules, both ladders place rounded orders and pay the maker fee.
+    let fee_model = FeeModel {
+        maker_fee: 0.001,
+        ..Default::default()
+    };
+    let rules = ExchangeRules {
+        price_tick: 0.01,
+        quantity_step: 0.0001,
+        min_notional: 10.0,
+    };
+    let run = |costs: bool| {
+        let mut amm = AMMReentry::new(
+            25000.0,
+            75000.0 / 33000.0,
+            33000.0,
+            CashRatioPath::Fixed(0.25),
+            0.005,
+            0.1,
+            0.2,
+        );
+        if costs {
+            amm.set_fee_model(fee_model);
+            amm.set_exchange_rules(rules);
+        }
+        let mut logs = vec![];
+        for i in 0..=20 {
+            amm.update(33000.0 + 650.0 * i as f64, &mut logs);
+        }
+        for i in 0..=20 {
+            amm.update(46000.0 - 500.0 * i as f64, &mut logs);
+        }
+        (amm.report(), logs)
+    };
+    let (free, _) = run(false);
+    let (report, logs) = run(true);
+    assert_eq!(free.fees_paid, 0.0);
+    assert!(report.fees_paid > 0.0 && report.coins_bought_back > 0.0);
+    assert!(logs.iter().all(|log| {
+        let steps = log.1.abs() / rules.quantity_step;
+        (steps - steps.round()).abs() < 1e-6 && log.0 * log.1.abs() >= rules.min_notional
+    }));
+    let bought: Vec<&(f64, f64)> = logs.iter().filter(|log| log.1 > 0.0).collect();
+    let notional: f64 = bought.iter().map(|log| log.0 * log.1).sum();
+    let buy_fees = fee_model.maker(notional);
+    assert!((report.cash_spent - notional - buy_fees).abs() < 1e-6);
+    assert!(report.coins_bought_back < free.coins_bought_back);
+}
+
+#[test]
+fn test_expiration_policies() {
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    assert_eq!(amm.check_expiration(9, 100.0), (false, 0.0, 0.0));
+    assert_eq!(amm.check_expiration(10, 100.0), (true, 1000.0, 0.0));
+
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    amm.set_expiration_policy(ExpirationPolicy::PriceRise(0.2));
+    assert!(!amm.check_expiration(100, 119.0).0);
+    assert!(amm.check_expiration(1, 120.0).0);
+
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    amm.set_expiration_policy(ExpirationPolicy::CashExhausted(0.4));
+    amm.buy(90.0, &mut vec![]);
+    assert!(!amm.check_expiration(100, 90.0).0);
+    amm.buy(50.0, &mut vec![]);
+    let (expire, cash, coins) = amm.check_expiration(1, 50.0);
+    assert!(expire && cash <= 400.0 && coins > 0.0);
+
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    amm.set_expiration_policy(ExpirationPolicy::Decay(0.1));
+    assert_eq!(amm.check_expiration(0, 100.0), (false, 100.0, 0.0));
+    assert_eq!(amm.check_expiration(1, 100.0), (false, 90.0, 0.0));
+    assert_eq!(amm.check_expiration(10, 100.0), (true, 810.0, 0.0));
+
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    amm.set_expiration_policy(ExpirationPolicy::NeverWithFloor(80.0));
+    let mut logs = vec![];
+    amm.buy(50.0, &mut logs);
+    assert!(!logs.is_empty() && logs.iter().all(|log| log.0 >= 80.0));
+    assert_eq!(amm.buy(40.0, &mut logs), (0.0, 0.0));
+    assert!(!amm.check_expiration(usize::MAX, 1000.0).0);
+
+    let mut amm = AMMBuyBear::new(1000.0, 100.0, 10, 0.5, 0.01);
+    assert_eq!(amm.release_cash(300.0), 300.0);
+    assert_eq!(amm.release_cash(1000.0), 700.0);
+    assert_eq!(
+        (amm.cash, amm.cash_released(), amm.start_cash()),
+        (0.0, 1000.0, 1000.0)
+    );
+
+    // Every policy other than `AtTick` puts the cash of expired AMMs, or the cash released by
+    // decay, in later AMMs, so the result differs.
+    let rows = load_history();
+    let rows = &rows[200..600];
+    let auto = |policy: ExpirationPolicy| {
+        let mut method = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 150, 0.01);
+        method.set_expiration_policy(policy);
+        run_method(&mut method, rows)
+    };
+    let amm = |policy: ExpirationPolicy| {
+        let mut method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+        method.set_expiration_policy(policy);
+        run_method(&mut method, rows)
+    };
+    let auto_at_tick = auto(ExpirationPolicy::AtTick);
+    let amm_at_tick = amm(ExpirationPolicy::AtTick);
+    assert!(auto_at_tick.0 > 0.0 && auto_at_tick.1 > 0.0);
+    assert!(amm_at_tick.0 > 0.0 && amm_at_tick.1 > 0.0);
+    for &policy in &[
+        ExpirationPolicy::PriceRise(0.1),
+        ExpirationPolicy::CashExhausted(0.9),
+        ExpirationPolicy::Decay(0.05),
+        ExpirationPolicy::NeverWithFloor(5000.0),
+    ] {
+        assert_ne!(auto(policy), auto_at_tick, "{:?}", policy);
+        assert_ne!(amm(policy), amm_at_tick, "{:?}", policy);
+    }
+
+    // 250 of the 400 AMMs put are past their imaginary expiration, each was put with
+    // 2000 / 30 / (1 - 0.9) * 0.75.
+    let mut method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+    run_method(&mut method, rows);
+    let (cash_put, cash_invested, average_invested, _) = method.past_amm_cash_utilization();
+    assert!((cash_put - 250.0 * 500.0).abs() < 1e-6);
+    assert!(cash_invested > 0.0 && cash_invested < cash_put);
+    assert!((average_invested * 250.0 - cash_invested).abs() < 1e-6);
+
+    // On a long fall the AMMs run out of cash for the minimum order and are dropped, even when
+    // they never expire. Their coins stay invested.
+    fn fall<M: CostAverageMethodTrait + serde::Serialize>(mut method: M) -> (usize, f64, f64) {
+        method.set_supply(DOLLAR_COST_AVERAGE_SUPPLY.0);
+        method.start_new_round(DAYS_PER_ROUND);
+        for i in 0..300 {
+            method.feed_price(10000.0 * 0.97f64.powi(i));
+        }
+        let state = serde_json::to_value(&method).unwrap();
+        let amms = state["amms"].as_array().unwrap();
+        let amm_coins = amms.iter().map(|amm| amm["coins"].as_f64().unwrap()).sum();
+        (amms.len(), amm_coins, method.get_invest_status().1)
+    }
+    for &min_notional in &[0.0, 10.0] {
+        let rules = ExchangeRules {
+            min_notional,
+            ..ExchangeRules::default()
+        };
+        let policy = ExpirationPolicy::NeverWithFloor(0.0);
+        let mut amm = AMMCostAverage::new(0.75, 0.25, 0.005, 150);
+        amm.set_exchange_rules(rules);
+        amm.set_expiration_policy(policy);
+        let mut auto = AMMCostAverageAuto::new(0.75, 0.25, 0.005, 150, 0.01);
+        auto.set_exchange_rules(rules);
+        auto.set_expiration_policy(policy);
+        for (amms, amm_coins, coins) in [fall(amm), fall(auto)].iter() {
+            if min_notional == 0.0 {
+                assert_eq!(*amms, 300);
+                assert!((amm_coins - coins).abs() < 1e-9);
+            } else {
+                assert!(*amms < 150, "{}", amms);
+                assert!(amm_coins < coins);
+            }
+        }
+    }
+}
+
+fn load_history() -> Vec<Row> {
+    price_data::load("./src/bitcoin_price_hist_since_first_10000.tsv").unwrap()
+}
+
+/// Feeds `rows` to `method` in rounds of `DAYS_PER_ROUND` ticks with the supply of
+/// `DOLLAR_COST_AVERAGE_SUPPLY`.
+/// Returns: the invest status after the last row.
+fn run_method<M: CostAverageMethodTrait + ?Sized>(method: &mut M, rows: &[Row]) -> (f64, f64) {
+    invest_rounds(
+        method,
+        rows,
+        &RoundSchedule::FixedTicks(DAYS_PER_ROUND),
+        &SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0),
+        |_, _| {},
+    );
+    method.get_invest_status()
+}
+
+fn resume_from_checkpoint<M>(name: &str, new_method: impl Fn() -> M, rows: &[Row])
+where
+    M: CostAverageMethodTrait + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let run = |method: &mut M, from: usize, to: usize| {
+        let mut orders = vec![];
+        for (i, row) in rows.iter().enumerate().take(to).skip(from) {
+            if i % DAYS_PER_ROUND == 0 {
+                method.start_new_round(DAYS_PER_ROUND);
+            }
+            method.feed_bar(row);
+            orders.extend(method.take_orders());
+        }
+        orders
+    };
+    let half = rows.len() / 2 + 7;
+
+    let mut method = new_method();
+    method.set_supply(DOLLAR_COST_AVERAGE_SUPPLY.0);
+    let orders = run(&mut method, 0, rows.len());
+
+    let mut first = new_method();
+    first.set_supply(DOLLAR_COST_AVERAGE_SUPPLY.0);
+    let mut resumed_orders = run(&mut first, 0, half);
+    let path = std::env::temp_dir().join(format!("{}_{}.json", name, std::process::id()));
+    checkpoint::save(&first, &path).unwrap();
+    drop(first);
+    let mut resumed: M = checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    resumed_orders.extend(run(&mut resumed, half, rows.len()));
+
+    assert_eq!(resumed.get_invest_status(), method.get_invest_status());
+    assert_eq!(resumed.get_fees_paid(), method.get_fees_paid());
+    assert_eq!(resumed_orders, orders);
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    let rows = load_history();
+    let rows = &rows[200..600];
+    let fee_model = FeeModel {
+        maker_fee: 0.001,
+        taker_fee: 0.002,
+        fixed_fee: 0.0,
+        spread: 0.001,
+    };
+
+    resume_from_checkpoint("dca", DollarCostAverage::default, rows);
+    resume_from_checkpoint("daily_dca", DailyDollarCostAverage::default, rows);
+    resume_from_checkpoint(
+        "amm_cost_average",
+        || {
+            let mut method = AMMCostAverage::new(0.75, 0.9, 0.005, 150);
+            method.set_fee_model(fee_model);
+            method
+        },
+        rows,
+    );
+    resume_from_checkpoint(
+        "amm_cost_average_auto",
+        || {
+            let mut method = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 150, 0.01);
+            method.set_expiration_policy(ExpirationPolicy::Decay(0.01));
+            method
+        },
+        rows,
+    );
+
+    let path = std::env::temp_dir().join("no_such_checkpoint.json");
+    assert!(checkpoint::load::<DollarCostAverage, _>(&path).is_err());
+}
+
+#[test]
+fn test_execution_modes() {
+    let time = NaiveDate::from_ymd_opt(2021, 2, 9)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    // The daily close jumps from 38871 to 46375.
+    let bar = Row::new(time, 46375.9);
+    let run = |mode: ExecutionMode| {
+        let mut amm = AMMSellBull::new(10000.0, 1.0, 38871.4, 0.25, 0.005);
+        amm.set_execution_mode(mode);
+        let mut fills = vec![];
+        let (cash, coins) = amm.execute_bar(&bar, IntrabarOrder::default(), &mut fills);
+        assert!((amm.cash - 10000.0 - cash).abs() < 1e-6);
+        assert!((1.0 - amm.coins - coins).abs() < 1e-12);
+        (amm.cash + amm.coins * bar.price, amm.last_price, fills)
+    };
+
+    let (resting, resting_last, fills) = run(ExecutionMode::RestingLimit);
+    assert!(fills.len() > 30);
+    assert!(fills.iter().all(|fill| fill.price_improvement() == 0.0));
+
+    let (gap, gap_last, fills) = run(ExecutionMode::GapAtOpen);
+    assert!(fills.len() > 30);
+    assert!(fills.iter().all(|fill| fill.fill_price == 46375.9));
+    assert!(fills.iter().all(|fill| fill.price_improvement() >= 0.0));
+    let improvement: f64 = fills.iter().map(|fill| fill.price_improvement()).sum();
+    assert!((gap - resting - improvement).abs() < 1e-6);
+
+    let (market, market_last, fills) = run(ExecutionMode::MarketAtBar);
+    assert_eq!(fills.len(), 1);
+    assert!(fills[0].level_price < 46375.9 && fills[0].price_improvement() > 0.0);
+    assert!(market >= gap && gap > resting);
+    assert_eq!(resting_last, gap_last);
+    assert_eq!(resting_last, market_last);
+
+    // With an open, only the levels gapped over before the open fill at the open.
+    let bar = Row::new_bar(time, 40000.0, 47000.0, 39500.0, 46375.9, None);
+    let mut amm = AMMSellBull::new(10000.0, 1.0, 38871.4, 0.25, 0.005);
+    amm.set_execution_mode(ExecutionMode::GapAtOpen);
+    let mut fills = vec![];
+    amm.execute_bar(&bar, IntrabarOrder::default(), &mut fills);
+    assert!(fills
+        .iter()
+        .all(|fill| (fill.level_price < 40000.0) == (fill.fill_price == 40000.0)));
+    assert!(fills.iter().any(|fill| fill.level_price > 40000.0));
+
+    // The mode of an exit ladder reaches its fills.
+    let mut ladder = ExitLadder::new(0.25, 0.005);
+    ladder.set_execution_mode(ExecutionMode::GapAtOpen);
+    let (mut cash, mut coins, mut price) = (10000.0, 1.0, 38871.4);
+    let mut ladder_fills = vec![];
+    ladder.exit_bar(
+        &mut cash,
+        &mut coins,
+        &mut price,
+        &bar,
+        IntrabarOrder::default(),
+        &mut ladder_fills,
+    );
+    assert_eq!(ladder_fills, fills);
+    assert_eq!((cash, coins, price), (amm.cash, amm.coins, amm.last_price));
+}
+
+#[test]
+fn test_round_schedule() {
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let payday = |schedule: RoundSchedule, day| schedule.payday_on_or_before(day).unwrap();
+    assert_eq!(
+        payday(RoundSchedule::Weekly, date(2021, 1, 13)),
+        date(2021, 1, 11)
+    );
+    assert_eq!(
+        payday(RoundSchedule::Weekly, date(2021, 1, 11)),
+        date(2021, 1, 11)
+    );
+    let biweekly = payday(RoundSchedule::Biweekly, date(2021, 1, 13));
+    assert_eq!(chrono::Datelike::weekday(&biweekly), chrono::Weekday::Mon);
+    assert_eq!(
+        RoundSchedule::Biweekly.next_payday(date(2021, 1, 13)),
+        Some(biweekly + chrono::Duration::weeks(2))
+    );
+    assert_eq!(
+        payday(RoundSchedule::MonthlyOnDay(31), date(2021, 3, 15)),
+        date(2021, 2, 28)
+    );
+    assert_eq!(
+        RoundSchedule::MonthlyOnDay(31).next_payday(date(2021, 3, 15)),
+        Some(date(2021, 3, 31))
+    );
+    assert_eq!(
+        payday(RoundSchedule::LastBusinessDay, date(2021, 1, 31)),
+        date(2021, 1, 29)
+    );
+    assert_eq!(
+        payday(RoundSchedule::LastBusinessDay, date(2021, 1, 28)),
+        date(2020, 12, 31)
+    );
+    assert_eq!(
+        RoundSchedule::FixedTicks(30).payday_on_or_before(date(2021, 1, 1)),
+        None
+    );
+
+    // Daily rows through 2021, with a round on the 1st of every month.
+    let rows: Vec<Row> = (0..365)
+        .map(|i| {
+            let time = date(2021, 1, 1).and_hms_opt(0, 0, 0).unwrap() + chrono::Duration::days(i);
+            Row::new(time, 30000.0 + 5000.0 * (i as f64 / 7.0).sin())
+        })
+        .collect();
+    let rounds = RoundSchedule::MonthlyOnDay(1).rounds(&rows);
+    assert_eq!(rounds.len(), 12);
+    assert_eq!(rounds[1], (31, 28));
+    assert_eq!(
+        rounds.iter().map(|round| round.1).sum::<usize>(),
+        rows.len()
+    );
+    let rounds = RoundSchedule::FixedTicks(30).rounds(&rows);
+    assert_eq!(rounds.len(), 13);
+    assert_eq!(rounds[12], (360, 30));
+    assert_eq!(RoundSchedule::Weekly.rounds(&rows)[1], (3, 7));
+    assert!(std::panic::catch_unwind(|| RoundSchedule::FixedTicks(0).rounds(&rows)).is_err());
+
+    let (cash, _coins, _price) = dollar_cost_average(
+        rows.len() - 1,
+        0,
+        0.0,
+        0,
+        0.0,
+        0.0,
+        &mut DailyDollarCostAverage::default(),
+        &rows,
+        &RoundSchedule::MonthlyOnDay(1),
+        &SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0),
+    );
+    assert!((cash - 12.0 * DOLLAR_COST_AVERAGE_SUPPLY.0).abs() < 1e-6);
+    let (cash, coins, _price) = dollar_cost_average(
+        rows.len() - 1,
+        0,
+        0.0,
+        0,
+        0.0,
+        0.0,
+        &mut AMMCostAverage::new(0.75, 0.9, 0.005, 60),
+        &rows,
+        &RoundSchedule::LastBusinessDay,
+        &SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0),
+    );
+    assert!(cash > 0.0 && coins > 0.0);
+
+    let mut driver = stream::StreamDriver::with_schedule(
+        DailyDollarCostAverage::default(),
+        2000.0,
+        RoundSchedule::MonthlyOnDay(1),
+    );
+    let mut new_rounds = vec![];
+    driver.run(rows.clone(), |report| {
+        if report.new_round {
+            new_rounds.push(report.tick);
+        }
+    });
+    assert_eq!(
+        new_rounds,
+        vec![0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334]
+    );
+    assert!((driver.method().get_invest_status().0 - 24000.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_supply_schedule() {
+    let mut schedule = SupplySchedule::new(2000.0);
+    schedule.add_raise(6, 2500.0);
+    schedule.add_raise(3, 2200.0);
+    schedule.add_bonus(4, 5000.0);
+    schedule.add_pause(4, 2);
+    let deposits = schedule.clone();
+    schedule.add_withdrawal(8, 10000.0);
+    let supplies: Vec<f64> = (0..10).map(|round| schedule.supply(round)).collect();
+    assert_eq!(
+        supplies,
+        vec![2000.0, 2000.0, 2000.0, 2200.0, 5000.0, 0.0, 2500.0, 2500.0, -7500.0, 2500.0]
+    );
+
+    let rows = load_history();
+    let rows = &rows[200..530];
+    let round_schedule = RoundSchedule::FixedTicks(DAYS_PER_ROUND);
+    let flat = SupplySchedule::new(2000.0);
+    // Returns (cash invested, coins invested, cash received).
+    let invest = |method: &mut dyn CostAverageMethodTrait, supply_schedule: &SupplySchedule| {
+        for (round, (begin, ticks)) in round_schedule.rounds(rows).into_iter().enumerate() {
+            method.set_supply(supply_schedule.supply(round));
+            method.start_new_round(ticks);
+            for row in &rows[begin..(begin + ticks).min(rows.len())] {
+                method.feed_bar(row);
+            }
+        }
+        let (cash, coins) = method.get_invest_status();
+        (cash, coins, method.get_sell_status().0)
+    };
+
+    // 11 rounds, the deposits add 200 + 3000 - 2000 + 5 * 500.
+    let (cash, _, received) = invest(&mut DollarCostAverage::default(), &flat);
+    assert!((cash - 22000.0).abs() < 1e-6 && received == 0.0);
+    let (cash, coins, _) = invest(&mut DollarCostAverage::default(), &deposits);
+    assert!((cash - 25700.0).abs() < 1e-6);
+    // Round 8 buys nothing and sells for 7500.
+    let (schedule_cash, schedule_coins, received) =
+        invest(&mut DollarCostAverage::default(), &schedule);
+    assert!((cash - schedule_cash - 2500.0).abs() < 1e-6 && schedule_coins < coins);
+    assert!((received - 7500.0).abs() < 1e-6);
+    let (cash, _, _) = invest(&mut DailyDollarCostAverage::default(), &deposits);
+    let (schedule_cash, _, received) = invest(&mut DailyDollarCostAverage::default(), &schedule);
+    assert!((cash - schedule_cash - 2500.0).abs() < 1e-6 && (received - 7500.0).abs() < 1e-6);
+
+    // The AMMs give up their uninvested cash before any coins.
+    let (_, coins, _) = invest(&mut AMMCostAverage::new(0.75, 0.9, 0.005, 60), &deposits);
+    let (_, schedule_coins, _) = invest(&mut AMMCostAverage::new(0.75, 0.9, 0.005, 60), &schedule);
+    assert!(schedule_coins < coins && schedule_coins > 0.0);
+    let (_, coins, _) = invest(
+        &mut AMMCostAverageAuto::new(0.75, 0.9, 0.005, 60, 0.1),
+        &deposits,
+    );
+    let (_, schedule_coins, _) = invest(
+        &mut AMMCostAverageAuto::new(0.75, 0.9, 0.005, 60, 0.1),
+        &schedule,
+    );
+    assert!(schedule_coins < coins && schedule_coins > 0.0);
+
+    // Enough is sold to receive the withdrawal after fees, the unmet part is recorded.
+    let fee_model = FeeModel {
+        maker_fee: 0.001,
+        taker_fee: 0.002,
+        fixed_fee: 1.0,
+        spread: 0.001,
+    };
+    let mut dca = DollarCostAverage::default();
+    dca.set_fee_model(fee_model);
+    dca.set_supply(-1000.0);
+    dca.start_new_round(1);
+    dca.feed_price(100.0);
+    assert_eq!(dca.take_orders(), vec![]);
+    assert_eq!(dca.get_withdrawal_shortfall(), 1000.0);
+    dca.set_supply(5000.0);
+    dca.start_new_round(1);
+    dca.feed_price(100.0);
+    dca.set_supply(-1000.0);
+    dca.start_new_round(1);
+    dca.feed_price(100.0);
+    let (cash, coins) = dca.get_invest_status();
+    assert_eq!(cash, 5000.0);
+    assert!((dca.get_sell_status().0 - 1000.0).abs() < 1e-9);
+    assert_eq!(dca.get_withdrawal_shortfall(), 1000.0);
+    dca.set_supply(-10000.0);
+    dca.start_new_round(1);
+    dca.feed_price(100.0);
+    let received = dca.get_sell_status().0 - 1000.0;
+    assert!(received < coins * 100.0 && dca.get_invest_status() == (cash, 0.0));
+    assert!((dca.get_withdrawal_shortfall() - (11000.0 - received)).abs() < 1e-9);
+    // The average purchase price counts the coins sold as bought.
+    let average_price = average_purchase_price(&dca);
+    assert!(average_price > 100.0 && average_price < 101.0);
+
+    let mut amm = AMMCostAverageAuto::new(0.75, 0.9, 0.005, 60, 0.1);
+    amm.set_fee_model(fee_model);
+    invest(&mut amm, &deposits);
+    let (_, coins) = amm.get_invest_status();
+    let price = rows[329].price;
+    amm.set_supply(-1.0e6);
+    amm.feed_price(price);
+    assert_eq!(amm.get_invest_status().1, 0.0);
+    // The uninvested cash of the AMMs is taken out too.
+    let shortfall = amm.get_withdrawal_shortfall();
+    assert!(shortfall > 0.0 && shortfall < 1.0e6 - coins * price * 0.99);
+
+    // Checkpoints from before the withdrawals still load.
+    let mut dca = DollarCostAverage::default();
+    dca.set_supply(2000.0);
+    dca.start_new_round(1);
+    dca.feed_price(100.0);
+    let mut state = serde_json::to_value(&dca).unwrap();
+    let fields = state.as_object_mut().unwrap();
+    assert!(
+        fields.remove("withdrawal").is_some() && fields.remove("withdrawal_shortfall").is_some()
+    );
+    let loaded: DollarCostAverage = serde_json::from_value(state).unwrap();
+    assert_eq!(loaded.get_invest_status(), dca.get_invest_status());
+    let mut state = serde_json::to_value(AMMCostAverage::new(0.75, 0.9, 0.005, 60)).unwrap();
+    state.as_object_mut().unwrap().remove("withdrawal");
+    assert!(serde_json::from_value::<AMMCostAverage>(state).is_ok());
+}
+
+#[test]
+fn test_value_average() {
+    // One round per price, the target grows by 1000 a round.
+    let run = |value_average: &mut ValueAverage| {
+        value_average.set_supply(1000.0);
+        for &price in &[100.0, 50.0, 100.0, 200.0] {
+            value_average.start_new_round(1);
+            value_average.feed_price(price);
+        }
+        value_average.take_orders()
+    };
+
+    let mut value_average = ValueAverage::new(0.0);
+    let orders = run(&mut value_average);
+    assert_eq!(
+        orders,
+        vec![(100.0, 10.0), (50.0, 30.0), (100.0, -10.0), (200.0, -10.0)]
+    );
+    // The purchases cost 2500 for 40 coins, the sales of 20 coins received 3000.
+    assert_eq!(value_average.get_invest_status(), (2500.0, 20.0));
+    assert_eq!(value_average.get_sell_status(), (3000.0, 20.0));
+    assert_eq!(average_purchase_price(&value_average), 62.5);
+    assert_eq!(value_average.target_value(), 4000.0);
+
+    let mut no_sell = ValueAverage::new(0.0);
+    no_sell.set_allow_sell(false);
+    let orders = run(&mut no_sell);
+    assert_eq!(orders, vec![(100.0, 10.0), (50.0, 30.0)]);
+    assert_eq!(no_sell.get_invest_status(), (2500.0, 40.0));
+
+    let mut capped = ValueAverage::new(0.0);
+    capped.set_max_round_buy(Some(1200.0));
+    let orders = run(&mut capped);
+    assert_eq!(orders[..3], [(100.0, 10.0), (50.0, 24.0), (100.0, -4.0)]);
+
+    // Withdrawals lower the target and are sold even without selling.
+    no_sell.set_supply(-3000.0);
+    no_sell.start_new_round(1);
+    no_sell.feed_price(200.0);
+    assert_eq!(no_sell.take_orders(), vec![(200.0, -15.0)]);
+    assert_eq!(no_sell.target_value(), 1000.0);
+
+    // It plugs into `dollar_cost_average` like the other methods.
+    let rows = load_history();
+    let mut value_average = ValueAverage::new(0.01);
+    value_average.set_fee_model(FeeModel {
+        taker_fee: 0.001,
+        spread: 0.0005,
+        ..Default::default()
+    });
+    let (cash, coins, average_price) = dollar_cost_average(
+        530,
+        200,
+        0.0,
+        300,
+        0.0,
+        0.0,
+        &mut value_average,
+        &rows,
+        &RoundSchedule::FixedTicks(DAYS_PER_ROUND),
+        &SupplySchedule::new(DOLLAR_COST_AVERAGE_SUPPLY.0),
+    );
+    assert!(cash > 0.0 && coins > 0.0 && average_price > 0.0);
+    assert!(value_average.get_fees_paid() > 0.0);
+}